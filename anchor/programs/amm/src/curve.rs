@@ -0,0 +1,657 @@
+//! Constant-product pricing used by the swap and liquidity instructions.
+//!
+//! Instructions price every pool through the `Curve` trait, which
+//! `ConstantProduct` implements over the functions below. The other curves
+//! live in their own modules, and the `CurveType` a pool is created with
+//! picks one of them. Adding a curve means implementing `Curve` in a new
+//! module and giving it a `CurveType`.
+//!
+//! Everything in here is pure arithmetic over token amounts so it can be
+//! exercised on the host and reused by off-chain quoting code.
+//!
+//! Rounding always favours the pool: swap outputs, withdrawals and minted
+//! liquidity round down, while required inputs and trading fees round up.
+//! Every division goes through `div_floor` or `div_ceil` to make the
+//! direction explicit.
+//!
+//! The 256-bit helpers at the top are shared with the other curves, whose
+//! intermediate products outgrow `u128`.
+
+use anchor_lang::prelude::*;
+
+use crate::{oracle, stable::StableCurve, weighted::WeightedCurve, TutorialError, MINIMUM_LIQUIDITY};
+
+/// Denominator of `Amm::fee`, which is expressed in basis points.
+pub const FEE_DENOMINATOR: u64 = 10000;
+
+/// Length of `Pool::curve_params`.
+pub const CURVE_PARAMS_LEN: usize = 32;
+
+/// Narrows an intermediate `u128` result back to a token amount.
+pub(crate) fn to_amount(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| error!(TutorialError::MathOverflow))
+}
+
+/// `numerator / denominator`, rounded down. Used for amounts leaving the pool.
+pub(crate) fn div_floor(numerator: u128, denominator: u128) -> Result<u64> {
+    let value = numerator
+        .checked_div(denominator)
+        .ok_or(TutorialError::MathOverflow)?;
+    to_amount(value)
+}
+
+/// `numerator / denominator`, rounded up. Used for amounts owed to the pool.
+pub(crate) fn div_ceil(numerator: u128, denominator: u128) -> Result<u64> {
+    if denominator == 0 {
+        return err!(TutorialError::MathOverflow);
+    }
+    to_amount(numerator.div_ceil(denominator))
+}
+
+/// Integer square root, rounded down.
+pub(crate) fn sqrt(value: u128) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+    // Newton's method from a power of two above the root only ever decreases
+    let mut root = 1u128 << (128 - value.leading_zeros()).div_ceil(2);
+    loop {
+        let next = (root + value / root) / 2;
+        if next >= root {
+            return root as u64;
+        }
+        root = next;
+    }
+}
+
+/// Full 256-bit product of `a` and `b`, as its high and low halves.
+pub(crate) fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    let (a_high, a_low) = (a >> 64, a as u64 as u128);
+    let (b_high, b_low) = (b >> 64, b as u64 as u128);
+
+    let (middle, middle_carry) = (a_high * b_low).overflowing_add(a_low * b_high);
+    let (low, low_carry) = (a_low * b_low).overflowing_add(middle << 64);
+    let high = a_high * b_high + (middle >> 64) + ((middle_carry as u128) << 64) + low_carry as u128;
+    (high, low)
+}
+
+/// `high · 2¹²⁸ + low` divided by `divisor`, rounded down.
+fn wide_div(high: u128, low: u128, divisor: u128) -> Result<u128> {
+    if divisor == 0 || high >= divisor {
+        return err!(TutorialError::MathOverflow);
+    }
+    if high == 0 {
+        return Ok(low / divisor);
+    }
+
+    // Long division, one bit of `low` at a time
+    let mut remainder = high;
+    let mut quotient = 0;
+    for bit in (0..128).rev() {
+        let overflow = remainder >> 127 == 1;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        if overflow || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1 << bit;
+        }
+    }
+    Ok(quotient)
+}
+
+/// `a * b / divisor`, rounded down, without overflowing on the product.
+pub(crate) fn mul_div(a: u128, b: u128, divisor: u128) -> Result<u128> {
+    let (high, low) = wide_mul(a, b);
+    wide_div(high, low, divisor)
+}
+
+/// `a * b / divisor`, rounded up, without overflowing on the product.
+pub(crate) fn mul_div_ceil(a: u128, b: u128, divisor: u128) -> Result<u128> {
+    let quotient = mul_div(a, b, divisor)?;
+    if wide_mul(quotient, divisor) == wide_mul(a, b) {
+        Ok(quotient)
+    } else {
+        checked(quotient.checked_add(1))
+    }
+}
+
+/// Result of a checked `u128` operation, `MathOverflow` if it overflowed.
+pub(crate) fn checked(value: Option<u128>) -> Result<u128> {
+    value.ok_or_else(|| error!(TutorialError::MathOverflow))
+}
+
+/// Fee of `fee` basis points charged on `amount`, rounded up.
+pub fn fee_amount(amount: u64, fee: u16) -> Result<u64> {
+    div_ceil(amount as u128 * fee as u128, FEE_DENOMINATOR as u128)
+}
+
+/// Removes the trading fee, rounded up, from `input`, returning the amount that is priced.
+pub fn apply_fee(input: u64, fee: u16) -> Result<u64> {
+    Ok(input - fee_amount(input, fee)?)
+}
+
+/// Smallest input that is left with at least `taxed_input` once `apply_fee` removes the fee.
+pub fn add_fee(taxed_input: u64, fee: u16) -> Result<u64> {
+    let untaxed = FEE_DENOMINATOR - fee as u64;
+    div_ceil(taxed_input as u128 * FEE_DENOMINATOR as u128, untaxed as u128)
+}
+
+/// Protocol share of a swap's `fee_amount`, with `protocol_fee` in basis points.
+///
+/// Rounds down so that any remainder stays in the pool with the LPs.
+pub fn protocol_fee(fee_amount: u64, protocol_fee: u16) -> Result<u64> {
+    div_floor(fee_amount as u128 * protocol_fee as u128, FEE_DENOMINATOR as u128)
+}
+
+/// Output of a swap of `input` tokens against the given reserves.
+///
+/// Returns the taxed input together with the output amount.
+pub fn swap_output(input: u64, fee: u16, reserve_in: u64, reserve_out: u64) -> Result<(u64, u64)> {
+    let taxed_input = apply_fee(input, fee)?;
+    let output = div_floor(
+        taxed_input as u128 * reserve_out as u128,
+        reserve_in as u128 + taxed_input as u128,
+    )?;
+    Ok((taxed_input, output))
+}
+
+/// Input needed for a swap to pay out exactly `output` tokens.
+///
+/// Solves the constant-product formula backwards and adds the trading fee on
+/// top, rounding up so the pool never receives less than it prices.
+/// Returns the input together with its taxed part.
+pub fn swap_input(output: u64, fee: u16, reserve_in: u64, reserve_out: u64) -> Result<(u64, u64)> {
+    if output >= reserve_out {
+        return err!(TutorialError::InsufficientLiquidity);
+    }
+
+    let taxed_input = div_ceil(output as u128 * reserve_in as u128, (reserve_out - output) as u128)?;
+    Ok((add_fee(taxed_input, fee)?, taxed_input))
+}
+
+/// Amounts of token A and B actually taken for a deposit into a pool.
+///
+/// Returns the largest pair at the reserve ratio that fits within
+/// `amount_a` and `amount_b`, rounding the matching amount up.
+pub fn deposit_amounts(amount_a: u64, amount_b: u64, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)> {
+    if reserve_a == 0 && reserve_b == 0 {
+        // Add as is if there is no liquidity
+        return Ok((amount_a, amount_b));
+    }
+
+    let matching_b = div_ceil(amount_a as u128 * reserve_b as u128, reserve_a as u128)?;
+    if matching_b <= amount_b {
+        return Ok((amount_a, matching_b));
+    }
+    // amount_a is worth more than amount_b, so the matching amount of A fits
+    let matching_a = div_ceil(amount_b as u128 * reserve_a as u128, reserve_b as u128)?;
+    Ok((matching_a, amount_b))
+}
+
+/// Liquidity tokens minted for depositing `amount_a` and `amount_b` into a
+/// pool holding `reserve_a` and `reserve_b` with `supply` liquidity tokens.
+///
+/// On pool creation `MINIMUM_LIQUIDITY` is held back from the depositor.
+/// Afterwards the deposit is valued at the smaller of its two shares of the
+/// reserves. Either way the result is rounded down.
+pub fn liquidity_to_mint(amount_a: u64, amount_b: u64, reserve_a: u64, reserve_b: u64, supply: u64) -> Result<u64> {
+    if supply == 0 {
+        let liquidity = sqrt(amount_a as u128 * amount_b as u128);
+
+        // Lock some minimum liquidity on the first deposit
+        if liquidity < MINIMUM_LIQUIDITY {
+            return err!(TutorialError::DepositTooSmall);
+        }
+        return Ok(liquidity - MINIMUM_LIQUIDITY);
+    }
+
+    let liquidity = div_floor(amount_a as u128 * supply as u128, reserve_a as u128)?
+        .min(div_floor(amount_b as u128 * supply as u128, reserve_b as u128)?);
+    if liquidity == 0 {
+        return err!(TutorialError::DepositTooSmall);
+    }
+    Ok(liquidity)
+}
+
+/// Amounts of token A and B paid out for burning `amount` of the `supply`
+/// liquidity tokens, rounded down.
+pub fn withdraw_amounts(amount: u64, reserve_a: u64, reserve_b: u64, supply: u64) -> Result<(u64, u64)> {
+    if amount > supply {
+        return err!(TutorialError::InsufficientLiquidity);
+    }
+    Ok((
+        div_floor(amount as u128 * reserve_a as u128, supply as u128)?,
+        div_floor(amount as u128 * reserve_b as u128, supply as u128)?,
+    ))
+}
+
+/// Pricing model of a pool.
+///
+/// Amounts are what the pool actually receives or sends, net of transfer
+/// fees, and every result is rounded in favour of the pool.
+pub trait Curve {
+    /// Output of a swap of `input` tokens, A for B if `swap_a`, with `fee` the
+    /// trading fee in basis points.
+    ///
+    /// Returns the taxed input together with the output amount.
+    fn swap_out(&self, swap_a: bool, input: u64, fee: u16, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)>;
+
+    /// Input needed for a swap to pay out exactly `output` tokens, A for B if `swap_a`.
+    ///
+    /// Returns the input together with its taxed part.
+    fn swap_in(&self, swap_a: bool, output: u64, fee: u16, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)>;
+
+    /// Parts of `amount_a` and `amount_b` a deposit takes, all of both unless
+    /// the curve only accepts some pairs.
+    fn deposit_amounts(&self, amount_a: u64, amount_b: u64, _reserve_a: u64, _reserve_b: u64) -> Result<(u64, u64)> {
+        Ok((amount_a, amount_b))
+    }
+
+    /// Liquidity tokens minted for depositing `amount_a` and `amount_b` into a
+    /// pool with `supply` liquidity tokens, with `fee` the trading fee.
+    ///
+    /// A curve taking deposits off the reserve ratio charges a trading fee
+    /// on the part that unbalances the pool, which would otherwise be a
+    /// fee-free swap. The fee stays in the reserves, so it goes to the LPs.
+    fn deposit(&self, amount_a: u64, amount_b: u64, fee: u16, reserve_a: u64, reserve_b: u64, supply: u64) -> Result<u64>;
+
+    /// Tokens A and B paid out for burning `liquidity` tokens out of `supply`.
+    ///
+    /// The same share of both reserves unless the curve says otherwise, which
+    /// leaves the price untouched on every curve.
+    fn withdraw(&self, liquidity: u64, reserve_a: u64, reserve_b: u64, supply: u64) -> Result<(u64, u64)> {
+        withdraw_amounts(liquidity, reserve_a, reserve_b, supply)
+    }
+
+    /// Marginal price of one unit of token A, or of token B unless `base_a`,
+    /// in units of the other token as a Q64.64 number. `None` when the
+    /// reserves cannot be priced.
+    fn spot_price(&self, base_a: bool, reserve_a: u64, reserve_b: u64) -> Option<u128>;
+
+    /// Value of the reserves, which no trade may decrease.
+    fn invariant(&self, reserve_a: u64, reserve_b: u64) -> Result<u128>;
+}
+
+/// Curve a pool is created with, whose parameters are kept in `Pool::curve_params`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum CurveType {
+    /// `x · y = k`, without parameters
+    ConstantProduct,
+    /// StableSwap, with the amplification coefficient as a little-endian `u64`
+    Stable,
+    /// Weighted product, with the weights of token A and B in basis points as little-endian `u16`s
+    Weighted,
+}
+
+impl CurveType {
+    /// Curve of this type between mints of the given decimals.
+    ///
+    /// Fails if `params` are not valid for the curve.
+    pub fn load(self, params: &[u8; CURVE_PARAMS_LEN], decimals_a: u8, decimals_b: u8) -> Result<Box<dyn Curve>> {
+        Ok(match self {
+            Self::ConstantProduct => {
+                read_params::<0>(params)?;
+                Box::new(ConstantProduct)
+            }
+            Self::Stable => {
+                let amplification = u64::from_le_bytes(read_params(params)?);
+                Box::new(StableCurve::new(amplification, decimals_a, decimals_b)?)
+            }
+            Self::Weighted => {
+                let [a0, a1, b0, b1] = read_params(params)?;
+                Box::new(WeightedCurve::new(u16::from_le_bytes([a0, a1]), u16::from_le_bytes([b0, b1]))?)
+            }
+        })
+    }
+}
+
+/// First `N` bytes of `params`, requiring the rest to be zero.
+fn read_params<const N: usize>(params: &[u8; CURVE_PARAMS_LEN]) -> Result<[u8; N]> {
+    let (used, unused) = params.split_at(N);
+    if unused.iter().any(|byte| *byte != 0) {
+        return err!(TutorialError::InvalidCurveParams);
+    }
+    Ok(used.try_into().unwrap())
+}
+
+/// `x · y = k` over the functions of this module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConstantProduct;
+
+impl Curve for ConstantProduct {
+    fn swap_out(&self, swap_a: bool, input: u64, fee: u16, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)> {
+        if swap_a {
+            swap_output(input, fee, reserve_a, reserve_b)
+        } else {
+            swap_output(input, fee, reserve_b, reserve_a)
+        }
+    }
+
+    fn swap_in(&self, swap_a: bool, output: u64, fee: u16, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)> {
+        if swap_a {
+            swap_input(output, fee, reserve_a, reserve_b)
+        } else {
+            swap_input(output, fee, reserve_b, reserve_a)
+        }
+    }
+
+    /// Only the largest pair matching the pool's ratio is taken, the rest stays with the depositor.
+    fn deposit_amounts(&self, amount_a: u64, amount_b: u64, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)> {
+        deposit_amounts(amount_a, amount_b, reserve_a, reserve_b)
+    }
+
+    fn deposit(&self, amount_a: u64, amount_b: u64, _fee: u16, reserve_a: u64, reserve_b: u64, supply: u64) -> Result<u64> {
+        liquidity_to_mint(amount_a, amount_b, reserve_a, reserve_b, supply)
+    }
+
+    fn spot_price(&self, base_a: bool, reserve_a: u64, reserve_b: u64) -> Option<u128> {
+        if base_a {
+            oracle::spot_price(reserve_a, reserve_b)
+        } else {
+            oracle::spot_price(reserve_b, reserve_a)
+        }
+    }
+
+    fn invariant(&self, reserve_a: u64, reserve_b: u64) -> Result<u128> {
+        Ok(reserve_a as u128 * reserve_b as u128)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_is_taken_from_input() {
+        assert_eq!(apply_fee(1_000_000, 300).unwrap(), 970_000);
+        assert_eq!(apply_fee(1_000_000, 0).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn fee_rounds_up() {
+        assert_eq!(apply_fee(10_001, 1).unwrap(), 9_999);
+        // Dust trades pay their fee in full and buy nothing
+        assert_eq!(apply_fee(1, 1).unwrap(), 0);
+        assert_eq!(swap_output(1, 1, 100, 100_000_000).unwrap().1, 0);
+        // Even a one-token flash loan pays a fee
+        assert_eq!(fee_amount(1, 1).unwrap(), 1);
+        assert_eq!(fee_amount(u64::MAX, 10000).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn protocol_takes_its_share_of_the_fee() {
+        assert_eq!(protocol_fee(30_000, 5000).unwrap(), 15_000);
+        assert_eq!(protocol_fee(30_000, 0).unwrap(), 0);
+        assert_eq!(protocol_fee(30_000, 10000).unwrap(), 30_000);
+    }
+
+    #[test]
+    fn swap_output_follows_constant_product() {
+        let (taxed, output) = swap_output(1_000_000, 0, 100_000_000, 100_000_000).unwrap();
+        assert_eq!(taxed, 1_000_000);
+        // 1e6 * 1e8 / (1e8 + 1e6)
+        assert_eq!(output, 990_099);
+    }
+
+    #[test]
+    fn swap_output_supports_full_u64_reserves() {
+        let (_, output) = swap_output(u64::MAX / 2, 300, u64::MAX / 2, u64::MAX).unwrap();
+        // Selling as much as is in the pool, less the fee, buys just under half of it
+        assert!(output < u64::MAX / 2 && output > u64::MAX / 2 - u64::MAX / 50);
+        // 1e6 * MAX / (MAX + 1e6) falls just short of 1e6
+        assert_eq!(swap_output(1_000_000, 0, u64::MAX, u64::MAX).unwrap().1, 999_999);
+    }
+
+    #[test]
+    fn swap_input_reports_overflow() {
+        assert_eq!(
+            swap_input(u64::MAX - 1, 0, u64::MAX, u64::MAX).unwrap_err(),
+            TutorialError::MathOverflow.into()
+        );
+    }
+
+    #[test]
+    fn sqrt_rounds_down() {
+        assert_eq!(sqrt(0), 0);
+        assert_eq!(sqrt(1), 1);
+        assert_eq!(sqrt(15), 3);
+        assert_eq!(sqrt(16), 4);
+        assert_eq!(sqrt(u64::MAX as u128 * u64::MAX as u128), u64::MAX);
+        assert_eq!(sqrt(u128::MAX), u64::MAX);
+        assert_eq!(sqrt((1u128 << 100) - 1), (1u64 << 50) - 1);
+    }
+
+    #[test]
+    fn wide_arithmetic_matches_narrow_results() {
+        assert_eq!(wide_mul(u128::MAX, u128::MAX), (u128::MAX - 1, 1));
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX).unwrap(), u128::MAX);
+        assert_eq!(mul_div(1 << 100, 1 << 100, 1 << 90).unwrap(), 1 << 110);
+        assert_eq!(mul_div(1_000, 3, 7).unwrap(), 428);
+        assert_eq!(mul_div(u128::MAX, 2, 1).unwrap_err(), TutorialError::MathOverflow.into());
+        assert_eq!(mul_div(1, 1, 0).unwrap_err(), TutorialError::MathOverflow.into());
+    }
+
+    #[test]
+    fn swap_input_covers_requested_output() {
+        let (input, taxed) = swap_input(990_099, 0, 100_000_000, 100_000_000).unwrap();
+        assert_eq!(taxed, input);
+        assert!(swap_output(input, 0, 100_000_000, 100_000_000).unwrap().1 >= 990_099);
+        assert!(swap_output(input - 1, 0, 100_000_000, 100_000_000).unwrap().1 < 990_099);
+
+        let (input, _) = swap_input(1_000_000, 300, 100_000_000, 50_000_000).unwrap();
+        assert!(swap_output(input, 300, 100_000_000, 50_000_000).unwrap().1 >= 1_000_000);
+    }
+
+    #[test]
+    fn swap_input_rejects_draining_the_pool() {
+        assert_eq!(
+            swap_input(100, 0, 100, 100).unwrap_err(),
+            TutorialError::InsufficientLiquidity.into()
+        );
+    }
+
+    #[test]
+    fn first_deposit_locks_minimum_liquidity() {
+        assert_eq!(
+            liquidity_to_mint(100_000_000, 100_000_000, 0, 0, 0).unwrap(),
+            100_000_000 - MINIMUM_LIQUIDITY
+        );
+        assert_eq!(
+            liquidity_to_mint(u64::MAX, u64::MAX, 0, 0, 0).unwrap(),
+            u64::MAX - MINIMUM_LIQUIDITY
+        );
+        assert_eq!(
+            liquidity_to_mint(10, 10, 0, 0, 0).unwrap_err(),
+            TutorialError::DepositTooSmall.into()
+        );
+    }
+
+    #[test]
+    fn unbalanced_deposit_takes_the_matching_pair() {
+        // Pool priced at 2 B per A
+        assert_eq!(
+            deposit_amounts(1_000_000, 5_000_000, 100_000_000, 200_000_000).unwrap(),
+            (1_000_000, 2_000_000)
+        );
+        assert_eq!(
+            deposit_amounts(5_000_000, 1_000_000, 100_000_000, 200_000_000).unwrap(),
+            (500_000, 1_000_000)
+        );
+        assert_eq!(
+            deposit_amounts(1_000_000, 1_000_000, 0, 0).unwrap(),
+            (1_000_000, 1_000_000)
+        );
+    }
+
+    #[test]
+    fn deposit_mints_the_smaller_share() {
+        // 1% of token A but only 0.5% of token B
+        assert_eq!(
+            liquidity_to_mint(1_000_000, 1_000_000, 100_000_000, 200_000_000, 50_000_000).unwrap(),
+            250_000
+        );
+        // Large reserves and supply do not overflow
+        assert_eq!(
+            liquidity_to_mint(u64::MAX / 2, u64::MAX / 2, u64::MAX, u64::MAX, u64::MAX).unwrap(),
+            u64::MAX / 2
+        );
+    }
+
+    #[test]
+    fn withdrawal_pays_out_pro_rata() {
+        assert_eq!(
+            withdraw_amounts(25_000_000, 100_000_000, 200_000_000, 100_000_000).unwrap(),
+            (25_000_000, 50_000_000)
+        );
+        assert_eq!(
+            withdraw_amounts(1, 100_000_000, 200_000_000, 300_000_000).unwrap(),
+            (0, 0)
+        );
+        assert_eq!(
+            withdraw_amounts(2, 1, 1, 1).unwrap_err(),
+            TutorialError::InsufficientLiquidity.into()
+        );
+    }
+
+    #[test]
+    fn deposit_then_full_withdrawal_returns_the_deposit() {
+        let (reserve_a, reserve_b, supply) = (123_456_789, 987_654_321, 300_000_000);
+        let (amount_a, amount_b) = deposit_amounts(10_000_000, 90_000_000, reserve_a, reserve_b).unwrap();
+        let liquidity = liquidity_to_mint(amount_a, amount_b, reserve_a, reserve_b, supply).unwrap();
+
+        let (out_a, out_b) =
+            withdraw_amounts(liquidity, reserve_a + amount_a, reserve_b + amount_b, supply + liquidity).unwrap();
+        assert!(out_a <= amount_a && amount_a - out_a <= 1);
+        assert!(out_b <= amount_b && amount_b - out_b <= 8);
+    }
+
+    #[test]
+    fn curve_params_are_checked_against_the_type() {
+        let params = |bytes: &[u8]| {
+            let mut params = [0; CURVE_PARAMS_LEN];
+            params[..bytes.len()].copy_from_slice(bytes);
+            params
+        };
+
+        let constant_product = CurveType::ConstantProduct.load(&params(&[]), 6, 9).unwrap();
+        assert_eq!(
+            constant_product.swap_out(false, 1_000_000, 30, 2_000_000_000, 1_000_000_000).unwrap(),
+            swap_output(1_000_000, 30, 1_000_000_000, 2_000_000_000).unwrap()
+        );
+        assert!(CurveType::Stable.load(&params(&100u64.to_le_bytes()), 6, 9).is_ok());
+        assert!(CurveType::Weighted.load(&params(&[0x40, 0x1f, 0xd0, 0x07]), 6, 9).is_ok());
+
+        // Bytes past the parameters of the type must stay zero
+        for (curve_type, params) in [
+            (CurveType::ConstantProduct, params(&[1])),
+            (CurveType::Stable, params(&[100, 0, 0, 0, 0, 0, 0, 0, 1])),
+            (CurveType::Weighted, params(&[0x40, 0x1f, 0xd0, 0x07, 1])),
+        ] {
+            assert_eq!(curve_type.load(&params, 6, 9).err(), Some(TutorialError::InvalidCurveParams.into()));
+        }
+        assert_eq!(
+            CurveType::Stable.load(&params(&[]), 6, 9).err(),
+            Some(TutorialError::InvalidAmplification.into())
+        );
+        assert_eq!(
+            CurveType::Weighted.load(&params(&[0x10, 0x27]), 6, 9).err(),
+            Some(TutorialError::InvalidWeights.into())
+        );
+    }
+
+    #[test]
+    fn dust_deposit_is_rejected() {
+        // Half a unit of A matches 1 B and is rounded up against the depositor
+        let (amount_a, amount_b) = deposit_amounts(1, 1, 100_000_000, 200_000_000).unwrap();
+        assert_eq!((amount_a, amount_b), (1, 1));
+        assert_eq!(
+            liquidity_to_mint(amount_a, amount_b, 100_000_000, 200_000_000, 100_000_000).unwrap_err(),
+            TutorialError::DepositTooSmall.into()
+        );
+    }
+
+    /// Xorshift generator so the rounding tests of every curve cover many
+    /// reserves deterministically.
+    pub(crate) struct Rng(pub(crate) u64);
+
+    impl Rng {
+        pub(crate) fn between(&mut self, low: u64, high: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            low + self.0 % (high - low + 1)
+        }
+    }
+
+    const ROUNDS: usize = 10_000;
+    const MAX_AMOUNT: u64 = 1_000_000_000_000;
+
+    fn product(a: u64, b: u64) -> u128 {
+        a as u128 * b as u128
+    }
+
+    #[test]
+    fn swap_round_trips_never_gain() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..ROUNDS {
+            let fee = rng.between(0, 1000) as u16;
+            let (reserve_a, reserve_b) = (rng.between(1, MAX_AMOUNT), rng.between(1, MAX_AMOUNT));
+            let input = rng.between(1, MAX_AMOUNT);
+
+            let (_, output) = swap_output(input, fee, reserve_a, reserve_b).unwrap();
+            let (reserve_a, reserve_b) = (reserve_a + input, reserve_b - output);
+            let (_, back) = swap_output(output, fee, reserve_b, reserve_a).unwrap();
+            assert!(back <= input);
+            assert!(product(reserve_a - back, reserve_b + output) >= product(reserve_a - input, reserve_b + output));
+        }
+    }
+
+    #[test]
+    fn exact_output_swaps_never_underpay() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..ROUNDS {
+            let fee = rng.between(0, 1000) as u16;
+            let (reserve_in, reserve_out) = (rng.between(1, MAX_AMOUNT), rng.between(2, MAX_AMOUNT));
+            let output = rng.between(1, reserve_out - 1);
+
+            let Ok((input, _)) = swap_input(output, fee, reserve_in, reserve_out) else {
+                continue;
+            };
+            assert!(swap_output(input, fee, reserve_in, reserve_out).unwrap().1 >= output);
+            assert!(product(reserve_in + input, reserve_out - output) >= product(reserve_in, reserve_out));
+        }
+    }
+
+    #[test]
+    fn liquidity_round_trips_never_gain() {
+        let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+        for _ in 0..ROUNDS {
+            let (reserve_a, reserve_b) = (rng.between(1, MAX_AMOUNT), rng.between(1, MAX_AMOUNT));
+            let supply = rng.between(1, MAX_AMOUNT);
+
+            // Deposit then withdraw
+            let (amount_a, amount_b) =
+                deposit_amounts(rng.between(0, MAX_AMOUNT), rng.between(0, MAX_AMOUNT), reserve_a, reserve_b).unwrap();
+            if let Ok(liquidity) = liquidity_to_mint(amount_a, amount_b, reserve_a, reserve_b, supply) {
+                let (out_a, out_b) =
+                    withdraw_amounts(liquidity, reserve_a + amount_a, reserve_b + amount_b, supply + liquidity).unwrap();
+                assert!(out_a <= amount_a && out_b <= amount_b);
+                // Every liquidity token is backed by at least as much as before
+                assert!(product(reserve_a + amount_a, supply) >= product(reserve_a, supply + liquidity));
+                assert!(product(reserve_b + amount_b, supply) >= product(reserve_b, supply + liquidity));
+            }
+
+            // Withdraw then deposit the proceeds back
+            let liquidity = rng.between(1, supply);
+            let (out_a, out_b) = withdraw_amounts(liquidity, reserve_a, reserve_b, supply).unwrap();
+            assert!(product(reserve_a - out_a, supply) >= product(reserve_a, supply - liquidity));
+            assert!(product(reserve_b - out_b, supply) >= product(reserve_b, supply - liquidity));
+            if liquidity < supply && out_a < reserve_a && out_b < reserve_b {
+                let (reserve_a, reserve_b) = (reserve_a - out_a, reserve_b - out_b);
+                let (amount_a, amount_b) = deposit_amounts(out_a, out_b, reserve_a, reserve_b).unwrap();
+                if let Ok(minted) = liquidity_to_mint(amount_a, amount_b, reserve_a, reserve_b, supply - liquidity) {
+                    assert!(minted <= liquidity);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Repayment check for flash loans, read from the Instructions sysvar.
+//!
+//! `flash_borrow` sends tokens out before anything is repaid, so it only
+//! proceeds when the transaction goes on to call `flash_repay` on the same
+//! pool. If that instruction then fails, the whole transaction and the loan
+//! with it are rolled back.
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+    Discriminator,
+};
+
+use crate::{instruction, TutorialError};
+
+/// Position of `pool` in the accounts of `FlashRepay`.
+const REPAY_POOL_INDEX: usize = 1;
+
+/// Fails unless an instruction after the current one repays the flash loan of `pool`.
+pub fn require_repayment(instructions: &AccountInfo, pool: &Pubkey) -> Result<()> {
+    let current = load_current_index_checked(instructions)? as usize;
+    for index in current + 1.. {
+        let Ok(ix) = load_instruction_at_checked(index, instructions) else {
+            break;
+        };
+        if ix.program_id == crate::ID
+            && ix.data.starts_with(instruction::FlashRepay::DISCRIMINATOR)
+            && ix.accounts.get(REPAY_POOL_INDEX).is_some_and(|meta| meta.pubkey == *pool)
+        {
+            return Ok(());
+        }
+    }
+    err!(TutorialError::FlashLoanNotRepaid)
+}
@@ -2,8 +2,18 @@
 #![allow(unexpected_cfgs)]
 
 use anchor_lang::prelude::*;
-use anchor_spl::{associated_token::AssociatedToken, token::{self, Mint, Token, TokenAccount, Burn, MintTo, Transfer}};
-use fixed::types::I64F64;
+use anchor_spl::{associated_token::AssociatedToken, token_interface::{self, Mint, TokenAccount, TokenInterface, Burn, MintTo, TransferChecked}};
+
+pub mod curve;
+pub mod flash_loan;
+pub mod oracle;
+pub mod route;
+pub mod stable;
+pub mod transfer_fee;
+pub mod weighted;
+
+use curve::{Curve, CurveType, CURVE_PARAMS_LEN};
+use oracle::{Observation, Observations};
 
 declare_id!("BM1qcSpGfkGwiv32VcydapQmNMVRv1h13UrmsNtZhubV");
 
@@ -16,6 +26,12 @@ pub const AUTHORITY_SEED: &[u8] = b"authority";
 #[constant]
 pub const LIQUIDITY_SEED: &[u8] = b"liquidity";
 
+#[constant]
+pub const LOCK_SEED: &[u8] = b"lock";
+
+#[constant]
+pub const OBSERVATIONS_SEED: &[u8] = b"observations";
+
 #[program]
 pub mod amm {
     use super::*;
@@ -25,18 +41,97 @@ pub mod amm {
         amm.id = id;
         amm.admin = ctx.accounts.admin.key();
         amm.fee = fee;
+        amm.status = Status::Active;
+        amm.treasury = ctx.accounts.admin.key();
+
+        emit!(AmmCreated {
+            amm: amm.key(),
+            id,
+            admin: amm.admin,
+            fee,
+        });
+        Ok(())
+    }
+
+    pub fn update_fee(ctx: Context<UpdateFee>, fee: u16) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        amm.fee = fee;
+        emit!(AmmUpdated::new(amm.key(), amm));
+        Ok(())
+    }
+
+    pub fn propose_admin(ctx: Context<UpdateAmm>, new_admin: Pubkey) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        amm.pending_admin = new_admin;
+        emit!(AmmUpdated::new(amm.key(), amm));
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        amm.admin = ctx.accounts.pending_admin.key();
+        amm.pending_admin = Pubkey::default();
+        emit!(AmmUpdated::new(amm.key(), amm));
+        Ok(())
+    }
+
+    pub fn set_protocol_fee(ctx: Context<SetProtocolFee>, protocol_fee: u16, treasury: Pubkey) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        amm.protocol_fee = protocol_fee;
+        amm.treasury = treasury;
+        emit!(AmmUpdated::new(amm.key(), amm));
+        Ok(())
+    }
+
+    pub fn set_amm_status(ctx: Context<UpdateAmm>, status: Status) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        amm.status = status;
+        emit!(AmmUpdated::new(amm.key(), amm));
+        Ok(())
+    }
+
+    pub fn set_pool_status(ctx: Context<SetPoolStatus>, status: Status) -> Result<()> {
+        ctx.accounts.pool.status = status;
+        emit!(PoolStatusUpdated {
+            pool: ctx.accounts.pool.key(),
+            status,
+        });
         Ok(())
     }
 
-    pub fn create_pool(ctx: Context<CreatePool>) -> Result<()> {
+    pub fn create_pool(ctx: Context<CreatePool>, curve_type: CurveType, curve_params: [u8; CURVE_PARAMS_LEN]) -> Result<()> {
+        ctx.accounts.amm.status.require_trading()?;
+        curve_type.load(&curve_params, ctx.accounts.mint_a.decimals, ctx.accounts.mint_b.decimals)?;
+
         let pool = &mut ctx.accounts.pool;
         pool.amm = ctx.accounts.amm.key();
         pool.mint_a = ctx.accounts.mint_a.key();
         pool.mint_b = ctx.accounts.mint_b.key();
+        pool.status = Status::Active;
+        pool.curve_type = curve_type;
+        pool.curve_params = curve_params;
+        pool.decimals_a = ctx.accounts.mint_a.decimals;
+        pool.decimals_b = ctx.accounts.mint_b.decimals;
+        pool.last_update = Clock::get()?.unix_timestamp;
+
+        emit!(PoolCreated {
+            amm: pool.amm,
+            pool: pool.key(),
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            mint_liquidity: ctx.accounts.mint_liquidity.key(),
+            curve_type,
+            curve_params,
+        });
         Ok(())
     }
 
-    pub fn deposit_liquidity(ctx: Context<DepositLiquidity>, amount_a: u64, amount_b: u64) -> Result<()> {
+    pub fn deposit_liquidity(ctx: Context<DepositLiquidity>, amount_a: u64, amount_b: u64, min_liquidity_out: u64, deadline: Option<Deadline>) -> Result<()> {
+        Deadline::require_pending(deadline)?;
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_trading()?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.update_oracle()?;
+
         let mut amount_a = if amount_a > ctx.accounts.depositor_account_a.amount {
             ctx.accounts.depositor_account_a.amount
         } else {
@@ -48,74 +143,48 @@ pub mod amm {
             amount_b
         };
 
-        let pool_a = &ctx.accounts.pool_account_a;
-        let pool_b = &ctx.accounts.pool_account_b;
+        let (reserve_a, reserve_b) = ctx.accounts.pool.reserves();
+        let curve = ctx.accounts.pool.curve()?;
+        (amount_a, amount_b) = curve.deposit_amounts(amount_a, amount_b, reserve_a, reserve_b)?;
 
-        let pool_creation = pool_a.amount == 0 && pool_b.amount == 0;
-        (amount_a, amount_b) =  if pool_creation {
-            // Add as is if there is no liquidity
-            (amount_a, amount_b)
-        } else {
-            let ratio = I64F64::from_num(pool_a.amount)
-                .checked_mul(I64F64::from_num(pool_b.amount))
-                .unwrap();
-            if pool_a.amount > pool_b.amount {
-                (
-                    I64F64::from_num(amount_b)
-                        .checked_mul(ratio)
-                        .unwrap()
-                        .to_num::<u64>(),
-                    amount_b,
-                )
-            } else {
-                (
-                    amount_a,
-                    I64F64::from_num(amount_a)
-                        .checked_div(ratio)
-                        .unwrap()
-                        .to_num::<u64>(),
-                )
-            }
-        };
+        // Liquidity is minted for what the pool receives after transfer fees
+        let received_a = transfer_fee::amount_received(&ctx.accounts.mint_a.to_account_info(), amount_a)?;
+        let received_b = transfer_fee::amount_received(&ctx.accounts.mint_b.to_account_info(), amount_b)?;
 
         //computing the amount of liquidity about to be deposited
-        let mut liquidity = I64F64::from_num(amount_a)
-            .checked_mul(I64F64::from_num(amount_b))
-            .unwrap()
-            .sqrt()
-            .to_num::<u64>();
-
-        // Lock some minimum liquidity on the first deposit
-        if pool_creation {
-            if liquidity < MINIMUM_LIQUIDITY {
-                return err!(TutorialError::DepositTooSmall);
-            }
-
-            liquidity -= MINIMUM_LIQUIDITY;
+        let supply = ctx.accounts.mint_liquidity.supply;
+        let fee = ctx.accounts.amm.fee;
+        let liquidity = curve.deposit(received_a, received_b, fee, reserve_a, reserve_b, supply)?;
+        if liquidity < min_liquidity_out {
+            return err!(TutorialError::LiquidityTooSmall);
         }
 
         // transfer tokens to the pool
-        token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                ctx.accounts.token_program_a.to_account_info(),
+                TransferChecked {
                     from: ctx.accounts.depositor_account_a.to_account_info(),
+                    mint: ctx.accounts.mint_a.to_account_info(),
                     to: ctx.accounts.pool_account_a.to_account_info(),
                     authority: ctx.accounts.depositor.to_account_info()
                 },
             ),
-            amount_a
+            amount_a,
+            ctx.accounts.mint_a.decimals,
         )?;
-        token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                ctx.accounts.token_program_b.to_account_info(),
+                TransferChecked {
                     from: ctx.accounts.depositor_account_b.to_account_info(),
+                    mint: ctx.accounts.mint_b.to_account_info(),
                     to: ctx.accounts.pool_account_b.to_account_info(),
                     authority: ctx.accounts.depositor.to_account_info()
                 },
             ),
-            amount_b
+            amount_b,
+            ctx.accounts.mint_b.decimals,
         )?;
 
         //Mint the liquidity to user
@@ -129,7 +198,7 @@ pub mod amm {
         ];
 
         let signer_seeds = &[&authority_seeds[..]];
-        token::mint_to(
+        token_interface::mint_to(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 MintTo {
@@ -141,10 +210,47 @@ pub mod amm {
             ),
             liquidity
         )?;
+
+        // The first deposit locks MINIMUM_LIQUIDITY for good, so the supply never drops back to zero
+        if supply == 0 {
+            token_interface::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    MintTo {
+                        mint: ctx.accounts.mint_liquidity.to_account_info(),
+                        to: ctx.accounts.liquidity_lock.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                MINIMUM_LIQUIDITY
+            )?;
+        }
+
+        ctx.accounts.pool.credit_reserves(received_a, received_b)?;
+        ctx.accounts.pool_account_a.reload()?;
+        ctx.accounts.pool_account_b.reload()?;
+        ctx.accounts.pool.surplus(ctx.accounts.pool_account_a.amount, ctx.accounts.pool_account_b.amount)?;
+
+        let (reserve_a, reserve_b) = ctx.accounts.pool.reserves();
+        emit!(LiquidityDeposited {
+            pool: ctx.accounts.pool.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount_a: received_a,
+            amount_b: received_b,
+            liquidity,
+            reserve_a,
+            reserve_b,
+        });
         Ok(())
     }
 
-    pub fn withdraw_liquidity(ctx: Context<WithdrawLiquidity>, amount: u64) -> Result<()> {
+    pub fn withdraw_liquidity(ctx: Context<WithdrawLiquidity>, amount: u64, min_amount_a: u64, min_amount_b: u64, deadline: Option<Deadline>) -> Result<()> {
+        Deadline::require_pending(deadline)?;
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_withdrawals()?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.update_oracle()?;
+
         let authority_bump = ctx.bumps.pool_authority;
         let authority_seeds = &[
             &ctx.accounts.pool.amm.to_bytes(),
@@ -155,47 +261,51 @@ pub mod amm {
         ];
         let signer_seeds = &[&authority_seeds[..]];
 
+        let (reserve_a, reserve_b) = ctx.accounts.pool.reserves();
+        let supply = ctx.accounts.mint_liquidity.supply;
+        let (amount_a, amount_b) = ctx.accounts.pool.curve()?.withdraw(amount, reserve_a, reserve_b, supply)?;
+
+        // Slippage is checked on what reaches the depositor after transfer fees
+        if transfer_fee::amount_received(&ctx.accounts.mint_a.to_account_info(), amount_a)? < min_amount_a
+            || transfer_fee::amount_received(&ctx.accounts.mint_b.to_account_info(), amount_b)? < min_amount_b
+        {
+            return err!(TutorialError::WithdrawalTooSmall);
+        }
+
         //transfer tokens from the pool 
-        let amount_a = I64F64::from_num(amount)
-            .checked_mul(I64F64::from_num(ctx.accounts.pool_account_a.amount))
-            .unwrap()
-            .checked_div(I64F64::from_num(ctx.accounts.mint_liquidity.supply + MINIMUM_LIQUIDITY))
-            .unwrap()
-            .floor().to_num::<u64>();
-        token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                ctx.accounts.token_program_a.to_account_info(),
+                TransferChecked {
                     from: ctx.accounts.pool_account_a.to_account_info(),
+                    mint: ctx.accounts.mint_a.to_account_info(),
                     to: ctx.accounts.depositer_account_a.to_account_info(),
                     authority: ctx.accounts.pool_authority.to_account_info()
                 },
                 signer_seeds
             ),
-            amount_a
+            amount_a,
+            ctx.accounts.mint_a.decimals,
         )?;
 
-        let amount_b = I64F64::from_num(amount)
-            .checked_div(I64F64::from_num(ctx.accounts.pool_account_b.amount))
-            .unwrap()
-            .checked_div(I64F64::from_num(ctx.accounts.mint_liquidity.supply + MINIMUM_LIQUIDITY))
-            .unwrap().floor().to_num::<u64>();
-        token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                ctx.accounts.token_program_b.to_account_info(),
+                TransferChecked {
                     from: ctx.accounts.pool_account_b.to_account_info(),
+                    mint: ctx.accounts.mint_b.to_account_info(),
                     to: ctx.accounts.depositer_account_b.to_account_info(),
                     authority: ctx.accounts.pool_authority.to_account_info()
                 },
                 signer_seeds
             ),
-            amount_b
+            amount_b,
+            ctx.accounts.mint_b.decimals,
         )?;
 
         //burn the liquidity tokens 
         //it will fail if the amount is invalid
-        token::burn(
+        token_interface::burn(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 Burn {
@@ -206,10 +316,44 @@ pub mod amm {
             ),
             amount,
         )?;
+
+        ctx.accounts.pool.debit_reserves(amount_a, amount_b)?;
+        ctx.accounts.pool_account_a.reload()?;
+        ctx.accounts.pool_account_b.reload()?;
+        ctx.accounts.mint_liquidity.reload()?;
+        ctx.accounts.pool.surplus(ctx.accounts.pool_account_a.amount, ctx.accounts.pool_account_b.amount)?;
+        let (reserve_a_after, reserve_b_after) = ctx.accounts.pool.reserves();
+
+        // The remaining liquidity tokens must still be backed by their share of both reserves
+        let supply_after = ctx.accounts.mint_liquidity.supply as u128;
+        if (reserve_a_after as u128) * (supply as u128) < (reserve_a as u128) * supply_after
+            || (reserve_b_after as u128) * (supply as u128) < (reserve_b as u128) * supply_after
+        {
+            return err!(TutorialError::InvariantViolated);
+        }
+
+        let (reserve_a, reserve_b) = (reserve_a_after, reserve_b_after);
+        emit!(LiquidityWithdrawn {
+            pool: ctx.accounts.pool.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount_a,
+            amount_b,
+            liquidity: amount,
+            reserve_a,
+            reserve_b,
+        });
         Ok(())
     }
 
-    pub fn swap_exact_tokens_for_tokens(ctx: Context<SwapExactTokensForTokens>, swap_a: bool, input_amount: u64, min_output_amount: u64) -> Result<()> {
+    pub fn swap_exact_tokens_for_tokens(ctx: Context<SwapExactTokensForTokens>, swap_a: bool, input_amount: u64, min_output_amount: u64, deadline: Option<Deadline>) -> Result<()> {
+        Deadline::require_pending(deadline)?;
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_trading()?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.update_oracle()?;
+        if let Some(observations) = &ctx.accounts.observations {
+            observations.load_mut()?.record(ctx.accounts.pool.observation());
+        }
+
         let input = if swap_a && input_amount > ctx.accounts.trader_account_a.amount {
             ctx.accounts.trader_account_a.amount
         } else if !swap_a && input_amount > ctx.accounts.trader_account_b.amount {
@@ -219,112 +363,689 @@ pub mod amm {
         };
 
         // Apply trading fee, used to compute the output
-        let amm= &ctx.accounts.amm;
-        let taxed_input = input - input * amm.fee as u64 / 10000;
-
-        let pool_a = &ctx.accounts.pool_account_a;
-        let pool_b = &ctx.accounts.pool_account_b;
-        let output = if swap_a {
-            I64F64::from_num(taxed_input)
-                .checked_mul(I64F64::from_num(pool_b.amount))
-                .unwrap()
-                .checked_div(
-                    I64F64::from_num(pool_a.amount)
-                        .checked_add(I64F64::from_num(taxed_input))
-                        .unwrap(),
-                )
-                .unwrap()
+        let amm = &ctx.accounts.amm;
+        let (mint_in, mint_out) = if swap_a {
+            (ctx.accounts.mint_a.to_account_info(), ctx.accounts.mint_b.to_account_info())
+        } else {
+            (ctx.accounts.mint_b.to_account_info(), ctx.accounts.mint_a.to_account_info())
+        };
+
+        // Price what the pool receives after transfer fees
+        let input_received = transfer_fee::amount_received(&mint_in, input)?;
+        let (taxed_input, output) = ctx.accounts.pool.swap_output(swap_a, input_received, amm.fee)?;
+        let protocol_fee = curve::protocol_fee(input_received - taxed_input, amm.protocol_fee)?;
+
+        // Slippage is checked on what reaches the trader after transfer fees
+        let output_received = transfer_fee::amount_received(&mint_out, output)?;
+        if output_received < min_output_amount {
+            return err!(TutorialError::OutputTooSmall);
+        }
+
+        // Compute the invariant before the trade
+        let invariant = ctx.accounts.pool.invariant()?;
+
+        // transfer tokens to the pool
+        let authority_bump = ctx.bumps.pool_authority;
+        let authority_seeds = &[
+            &ctx.accounts.pool.amm.to_bytes(),
+            &ctx.accounts.mint_a.key().to_bytes(),
+            &ctx.accounts.mint_b.key().to_bytes(),
+            AUTHORITY_SEED,
+            &[authority_bump],
+        ];
+        let signer_seeds = &[&authority_seeds[..]];
+        if swap_a {
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program_a.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.trader_account_a.to_account_info(),
+                        mint: ctx.accounts.mint_a.to_account_info(),
+                        to: ctx.accounts.pool_account_a.to_account_info(),
+                        authority: ctx.accounts.trader.to_account_info()
+                    },
+                ),
+                input,
+                ctx.accounts.mint_a.decimals,
+            )?;
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program_b.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.pool_account_b.to_account_info(),
+                        mint: ctx.accounts.mint_b.to_account_info(),
+                        to: ctx.accounts.trader_account_b.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                output,
+                ctx.accounts.mint_b.decimals,
+            )?;
+        } else {
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program_b.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.trader_account_b.to_account_info(),
+                        mint: ctx.accounts.mint_b.to_account_info(),
+                        to: ctx.accounts.pool_account_b.to_account_info(),
+                        authority: ctx.accounts.trader.to_account_info()
+                    },
+                ),
+                input,
+                ctx.accounts.mint_b.decimals,
+            )?;
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program_a.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.pool_account_a.to_account_info(),
+                        mint: ctx.accounts.mint_a.to_account_info(),
+                        to: ctx.accounts.trader_account_a.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                output,
+                ctx.accounts.mint_a.decimals,
+            )?;
+        }
+
+        msg!("Traded {} tokens ({} after fees) for {}", input, taxed_input, output);
+
+        // Set aside the protocol's share of the fee, the rest of the input goes to the reserves
+        ctx.accounts.pool.record_swap(swap_a, input_received, output, protocol_fee)?;
+
+        // verify the invariant still holds
+        // reload accounts because of the CPIs
+        // we tolerate if the new invariant is higher because it means a rounding error for LPs
+        ctx.accounts.pool_account_a.reload()?;
+        ctx.accounts.pool_account_b.reload()?;
+        ctx.accounts.pool.surplus(ctx.accounts.pool_account_a.amount, ctx.accounts.pool_account_b.amount)?;
+        if invariant > ctx.accounts.pool.invariant()? {
+            return err!(TutorialError::InvariantViolated);
+        }
+        let (reserve_a, reserve_b) = ctx.accounts.pool.reserves();
+
+        emit!(Swapped {
+            pool: ctx.accounts.pool.key(),
+            trader: ctx.accounts.trader.key(),
+            swap_a,
+            input: input_received,
+            output,
+            fee: input_received - taxed_input,
+            protocol_fee,
+            reserve_a,
+            reserve_b,
+        });
+
+        Ok(())
+    }
+
+    pub fn swap_tokens_for_exact_tokens(ctx: Context<SwapExactTokensForTokens>, swap_a: bool, output_amount: u64, max_input_amount: u64, deadline: Option<Deadline>) -> Result<()> {
+        Deadline::require_pending(deadline)?;
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_trading()?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.update_oracle()?;
+        if let Some(observations) = &ctx.accounts.observations {
+            observations.load_mut()?.record(ctx.accounts.pool.observation());
+        }
+
+        // Solve for the input, fee included, that pays out exactly `output_amount`
+        let amm = &ctx.accounts.amm;
+        let (trader_in, pool_in, mint_in, token_program_in) = if swap_a {
+            (
+                &ctx.accounts.trader_account_a,
+                &ctx.accounts.pool_account_a,
+                &ctx.accounts.mint_a,
+                &ctx.accounts.token_program_a,
+            )
         } else {
-            I64F64::from_num(taxed_input)
-                .checked_mul(I64F64::from_num(pool_a.amount))
-                .unwrap()
-                .checked_div(
-                    I64F64::from_num(pool_b.amount)
-                        .checked_add(I64F64::from_num(taxed_input))
-                        .unwrap(),
-                )
-                .unwrap()
+            (
+                &ctx.accounts.trader_account_b,
+                &ctx.accounts.pool_account_b,
+                &ctx.accounts.mint_b,
+                &ctx.accounts.token_program_b,
+            )
+        };
+        let (pool_out, trader_out, mint_out, token_program_out) = if swap_a {
+            (
+                &ctx.accounts.pool_account_b,
+                &ctx.accounts.trader_account_b,
+                &ctx.accounts.mint_b,
+                &ctx.accounts.token_program_b,
+            )
+        } else {
+            (
+                &ctx.accounts.pool_account_a,
+                &ctx.accounts.trader_account_a,
+                &ctx.accounts.mint_a,
+                &ctx.accounts.token_program_a,
+            )
+        };
+
+        // Gross up both legs for transfer fees so the trader receives exactly `output_amount`
+        let output = transfer_fee::amount_to_send(&mint_out.to_account_info(), output_amount)?;
+        let (input_received, taxed_input) = ctx.accounts.pool.swap_input(swap_a, output, amm.fee)?;
+        let input = transfer_fee::amount_to_send(&mint_in.to_account_info(), input_received)?;
+        let protocol_fee = curve::protocol_fee(input_received - taxed_input, amm.protocol_fee)?;
+
+        if input > max_input_amount {
+            return err!(TutorialError::InputTooLarge);
         }
-        .to_num::<u64>();
-
-    if output < min_output_amount {
-        return err!(TutorialError::OutputTooSmall);
-    }
-
-    // COmpute the invariant before the trade
-    let invariant = pool_a.amount * pool_b.amount;
-
-    // transfer tokens to the pool
-    let authority_bump = ctx.bumps.pool_authority;
-    let authority_seeds = &[
-        &ctx.accounts.pool.amm.to_bytes(),
-        &ctx.accounts.mint_a.key().to_bytes(),
-        &ctx.accounts.mint_b.key().to_bytes(),
-        AUTHORITY_SEED,
-        &[authority_bump],
-    ];
-    let signer_seeds = &[&authority_seeds[..]];
-    if swap_a {
-        token::transfer(
+
+        // Compute the invariant before the trade
+        let invariant = ctx.accounts.pool.invariant()?;
+
+        let authority_bump = ctx.bumps.pool_authority;
+        let authority_seeds = &[
+            &ctx.accounts.pool.amm.to_bytes(),
+            &ctx.accounts.mint_a.key().to_bytes(),
+            &ctx.accounts.mint_b.key().to_bytes(),
+            AUTHORITY_SEED,
+            &[authority_bump],
+        ];
+        let signer_seeds = &[&authority_seeds[..]];
+        token_interface::transfer_checked(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.trader_account_a.to_account_info(),
-                    to: ctx.accounts.pool_account_a.to_account_info(),
+                token_program_in.to_account_info(),
+                TransferChecked {
+                    from: trader_in.to_account_info(),
+                    mint: mint_in.to_account_info(),
+                    to: pool_in.to_account_info(),
                     authority: ctx.accounts.trader.to_account_info()
                 },
             ),
             input,
+            mint_in.decimals,
         )?;
-        token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.pool_account_b.to_account_info(),
-                    to: ctx.accounts.trader_account_b.to_account_info(),
+                token_program_out.to_account_info(),
+                TransferChecked {
+                    from: pool_out.to_account_info(),
+                    mint: mint_out.to_account_info(),
+                    to: trader_out.to_account_info(),
                     authority: ctx.accounts.pool_authority.to_account_info()
                 },
                 signer_seeds
             ),
             output,
+            mint_out.decimals,
+        )?;
+
+        msg!("Traded {} tokens ({} after fees) for {}", input, taxed_input, output_amount);
+
+        // Set aside the protocol's share of the fee, the rest of the input goes to the reserves
+        ctx.accounts.pool.record_swap(swap_a, input_received, output, protocol_fee)?;
+
+        // verify the invariant still holds
+        // reload accounts because of the CPIs
+        ctx.accounts.pool_account_a.reload()?;
+        ctx.accounts.pool_account_b.reload()?;
+        ctx.accounts.pool.surplus(ctx.accounts.pool_account_a.amount, ctx.accounts.pool_account_b.amount)?;
+        if invariant > ctx.accounts.pool.invariant()? {
+            return err!(TutorialError::InvariantViolated);
+        }
+        let (reserve_a, reserve_b) = ctx.accounts.pool.reserves();
+
+        emit!(Swapped {
+            pool: ctx.accounts.pool.key(),
+            trader: ctx.accounts.trader.key(),
+            swap_a,
+            input: input_received,
+            output,
+            fee: input_received - taxed_input,
+            protocol_fee,
+            reserve_a,
+            reserve_b,
+        });
+
+        Ok(())
+    }
+
+    pub fn swap_route<'info>(ctx: Context<'_, '_, 'info, 'info, SwapRoute<'info>>, input_amount: u64, min_output_amount: u64, deadline: Option<Deadline>) -> Result<()> {
+        Deadline::require_pending(deadline)?;
+        let amm = &ctx.accounts.amm;
+        let chunks = ctx.remaining_accounts.chunks_exact(route::ACCOUNTS_PER_HOP);
+        if chunks.len() == 0 || !chunks.remainder().is_empty() {
+            return err!(TutorialError::InvalidRoute);
+        }
+
+        let mut hops: Vec<route::Hop> = Vec::with_capacity(chunks.len());
+        for accounts in chunks {
+            let mut hop = route::Hop::load(amm, accounts)?;
+            // Each quote assumes the pool's reserves are untouched by earlier hops
+            if hops.iter().any(|other| other.pool.key() == hop.pool.key()) {
+                return err!(TutorialError::InvalidRoute);
+            }
+            amm.status.max(hop.pool.status).require_trading()?;
+            hop.pool.require_unlocked()?;
+            hop.pool.update_oracle()?;
+            if let Some(observations) = &hop.observations {
+                observations.load_mut()?.record(hop.pool.observation());
+            }
+            hops.push(hop);
+        }
+
+        let input = if input_amount > ctx.accounts.trader_account_in.amount {
+            ctx.accounts.trader_account_in.amount
+        } else {
+            input_amount
+        };
+
+        // Quote the whole route before moving any tokens
+        let mut mint = ctx.accounts.mint_in.key();
+        let mut amount = transfer_fee::amount_received(&ctx.accounts.mint_in.to_account_info(), input)?;
+        let mut quotes = Vec::with_capacity(hops.len());
+        for hop in &hops {
+            let swap_a = hop.swap_a(&mint)?;
+            let (taxed_input, output) = hop.pool.swap_output(swap_a, amount, amm.fee)?;
+            quotes.push(route::Quote {
+                swap_a,
+                input: amount,
+                taxed_input,
+                output,
+                protocol_fee: curve::protocol_fee(amount - taxed_input, amm.protocol_fee)?,
+                invariant: hop.pool.invariant()?,
+            });
+
+            let (mint_out, _, _) = hop.side(!swap_a);
+            mint = mint_out.key();
+            amount = transfer_fee::amount_received(&mint_out.to_account_info(), output)?;
+        }
+        require_keys_eq!(mint, ctx.accounts.mint_out.key(), TutorialError::InvalidRoute);
+
+        // Slippage is checked once, on what reaches the trader
+        if amount < min_output_amount {
+            return err!(TutorialError::OutputTooSmall);
+        }
+
+        // transfer tokens to the first pool
+        let (_, first_pool_in, _) = hops[0].side(quotes[0].swap_a);
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program_in.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.trader_account_in.to_account_info(),
+                    mint: ctx.accounts.mint_in.to_account_info(),
+                    to: first_pool_in.to_account_info(),
+                    authority: ctx.accounts.trader.to_account_info()
+                },
+            ),
+            input,
+            ctx.accounts.mint_in.decimals,
         )?;
-    } else {
-        token::transfer(
+
+        // Each pool pays the next one directly, only the last one pays the trader
+        for (index, (hop, quote)) in hops.iter().zip(&quotes).enumerate() {
+            let (mint_out, pool_out, token_program_out) = hop.side(!quote.swap_a);
+            let destination = match (hops.get(index + 1), quotes.get(index + 1)) {
+                (Some(next), Some(next_quote)) => next.side(next_quote.swap_a).1.to_account_info(),
+                _ => ctx.accounts.trader_account_out.to_account_info(),
+            };
+
+            let authority_seeds = &[
+                &hop.pool.amm.to_bytes(),
+                &hop.pool.mint_a.to_bytes(),
+                &hop.pool.mint_b.to_bytes(),
+                AUTHORITY_SEED,
+                &[hop.authority_bump],
+            ];
+            let signer_seeds = &[&authority_seeds[..]];
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program_out.to_account_info(),
+                    TransferChecked {
+                        from: pool_out.to_account_info(),
+                        mint: mint_out.to_account_info(),
+                        to: destination,
+                        authority: hop.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                quote.output,
+                mint_out.decimals,
+            )?;
+        }
+
+        // verify the invariant of every pool still holds
+        // reload accounts because of the CPIs
+        for (hop, quote) in hops.iter_mut().zip(&quotes) {
+            hop.pool.record_swap(quote.swap_a, quote.input, quote.output, quote.protocol_fee)?;
+            hop.pool_account_a.reload()?;
+            hop.pool_account_b.reload()?;
+            hop.pool.surplus(hop.pool_account_a.amount, hop.pool_account_b.amount)?;
+            if quote.invariant > hop.pool.invariant()? {
+                return err!(TutorialError::InvariantViolated);
+            }
+            let (reserve_a, reserve_b) = hop.pool.reserves();
+            hop.pool.exit(&crate::ID)?;
+
+            emit!(Swapped {
+                pool: hop.pool.key(),
+                trader: ctx.accounts.trader.key(),
+                swap_a: quote.swap_a,
+                input: quote.input,
+                output: quote.output,
+                fee: quote.input - quote.taxed_input,
+                protocol_fee: quote.protocol_fee,
+                reserve_a,
+                reserve_b,
+            });
+        }
+
+        msg!("Routed {} tokens through {} pools for {}", input, hops.len(), amount);
+        Ok(())
+    }
+
+    pub fn collect_protocol_fees(ctx: Context<CollectProtocolFees>) -> Result<()> {
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_withdrawals()?;
+        ctx.accounts.pool.require_unlocked()?;
+
+        let authority_bump = ctx.bumps.pool_authority;
+        let authority_seeds = &[
+            &ctx.accounts.pool.amm.to_bytes(),
+            &ctx.accounts.mint_a.key().to_bytes(),
+            &ctx.accounts.mint_b.key().to_bytes(),
+            AUTHORITY_SEED,
+            &[authority_bump],
+        ];
+        let signer_seeds = &[&authority_seeds[..]];
+
+        let pool = &mut ctx.accounts.pool;
+        let (amount_a, amount_b) = (pool.protocol_fees_a, pool.protocol_fees_b);
+        pool.protocol_fees_a = 0;
+        pool.protocol_fees_b = 0;
+
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                ctx.accounts.token_program_a.to_account_info(),
+                TransferChecked {
                     from: ctx.accounts.pool_account_a.to_account_info(),
-                    to: ctx.accounts.trader_account_a.to_account_info(),
+                    mint: ctx.accounts.mint_a.to_account_info(),
+                    to: ctx.accounts.treasury_account_a.to_account_info(),
                     authority: ctx.accounts.pool_authority.to_account_info()
                 },
                 signer_seeds
             ),
-            input,
+            amount_a,
+            ctx.accounts.mint_a.decimals,
         )?;
-        token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.trader_account_b.to_account_info(),
-                    to: ctx.accounts.pool_account_b.to_account_info(),
-                    authority: ctx.accounts.trader.to_account_info()
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program_b.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.pool_account_b.to_account_info(),
+                    mint: ctx.accounts.mint_b.to_account_info(),
+                    to: ctx.accounts.treasury_account_b.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info()
                 },
+                signer_seeds
             ),
-            output,
+            amount_b,
+            ctx.accounts.mint_b.decimals,
+        )?;
+
+        msg!("Collected {} and {} in protocol fees", amount_a, amount_b);
+
+        emit!(ProtocolFeesCollected {
+            pool: ctx.accounts.pool.key(),
+            treasury: ctx.accounts.treasury.key(),
+            amount_a,
+            amount_b,
+        });
+        Ok(())
+    }
+
+    pub fn skim(ctx: Context<Skim>) -> Result<()> {
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_withdrawals()?;
+        ctx.accounts.pool.require_unlocked()?;
+
+        let authority_bump = ctx.bumps.pool_authority;
+        let authority_seeds = &[
+            &ctx.accounts.pool.amm.to_bytes(),
+            &ctx.accounts.mint_a.key().to_bytes(),
+            &ctx.accounts.mint_b.key().to_bytes(),
+            AUTHORITY_SEED,
+            &[authority_bump],
+        ];
+        let signer_seeds = &[&authority_seeds[..]];
+
+        // Tokens sent straight to the pool accounts never count towards the reserves
+        let (amount_a, amount_b) = ctx.accounts.pool.surplus(
+            ctx.accounts.pool_account_a.amount,
+            ctx.accounts.pool_account_b.amount,
+        )?;
+
+        if amount_a > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program_a.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.pool_account_a.to_account_info(),
+                        mint: ctx.accounts.mint_a.to_account_info(),
+                        to: ctx.accounts.recipient_account_a.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                amount_a,
+                ctx.accounts.mint_a.decimals,
+            )?;
+        }
+        if amount_b > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program_b.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.pool_account_b.to_account_info(),
+                        mint: ctx.accounts.mint_b.to_account_info(),
+                        to: ctx.accounts.recipient_account_b.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                amount_b,
+                ctx.accounts.mint_b.decimals,
+            )?;
+        }
+
+        emit!(Skimmed {
+            pool: ctx.accounts.pool.key(),
+            recipient_account_a: ctx.accounts.recipient_account_a.key(),
+            recipient_account_b: ctx.accounts.recipient_account_b.key(),
+            amount_a,
+            amount_b,
+        });
+        Ok(())
+    }
+
+    pub fn sync(ctx: Context<SyncPool>) -> Result<()> {
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_withdrawals()?;
+        ctx.accounts.pool.require_unlocked()?;
+        ctx.accounts.pool.update_oracle()?;
+        let (amount_a, amount_b) = ctx.accounts.pool.surplus(
+            ctx.accounts.pool_account_a.amount,
+            ctx.accounts.pool_account_b.amount,
         )?;
+        ctx.accounts.pool.credit_reserves(amount_a, amount_b)?;
+
+        let (reserve_a, reserve_b) = ctx.accounts.pool.reserves();
+        emit!(Synced {
+            pool: ctx.accounts.pool.key(),
+            amount_a,
+            amount_b,
+            reserve_a,
+            reserve_b,
+        });
+        Ok(())
+    }
+
+    pub fn update_oracle(ctx: Context<UpdateOracle>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.update_oracle()?;
+        if let Some(observations) = &ctx.accounts.observations {
+            observations.load_mut()?.record(pool.observation());
+        }
+
+        emit!(OracleUpdated {
+            pool: pool.key(),
+            price_a_cumulative: pool.price_a_cumulative,
+            price_b_cumulative: pool.price_b_cumulative,
+            liquidity_cumulative: pool.liquidity_cumulative,
+            last_update: pool.last_update,
+        });
+        Ok(())
+    }
+
+    /// Accumulators of the pool `seconds_ago` seconds before now, see `Observations::consult`.
+    pub fn consult(ctx: Context<Consult>, seconds_ago: u32) -> Result<Observation> {
+        ctx.accounts.observations.load()?.consult(&ctx.accounts.pool, seconds_ago)
+    }
+
+    pub fn flash_borrow(ctx: Context<FlashBorrow>, amount_a: u64, amount_b: u64) -> Result<()> {
+        ctx.accounts.amm.status.max(ctx.accounts.pool.status).require_trading()?;
+        ctx.accounts.pool.require_unlocked()?;
+        flash_loan::require_repayment(&ctx.accounts.instructions, &ctx.accounts.pool.key())?;
+
+        let (reserve_a, reserve_b) = ctx.accounts.pool.reserves();
+        if amount_a > reserve_a || amount_b > reserve_b {
+            return err!(TutorialError::InsufficientLiquidity);
+        }
+        let fee = ctx.accounts.amm.fee;
+        let loan = FlashLoan {
+            amount_a,
+            amount_b,
+            fee_a: curve::fee_amount(amount_a, fee)?,
+            fee_b: curve::fee_amount(amount_b, fee)?,
+        };
+        // Locks the pool until `flash_repay`
+        ctx.accounts.pool.flash_loan = Some(loan);
+
+        let authority_bump = ctx.bumps.pool_authority;
+        let authority_seeds = &[
+            &ctx.accounts.pool.amm.to_bytes(),
+            &ctx.accounts.mint_a.key().to_bytes(),
+            &ctx.accounts.mint_b.key().to_bytes(),
+            AUTHORITY_SEED,
+            &[authority_bump],
+        ];
+        let signer_seeds = &[&authority_seeds[..]];
+
+        if amount_a > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program_a.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.pool_account_a.to_account_info(),
+                        mint: ctx.accounts.mint_a.to_account_info(),
+                        to: ctx.accounts.borrower_account_a.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                amount_a,
+                ctx.accounts.mint_a.decimals,
+            )?;
+        }
+        if amount_b > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program_b.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.pool_account_b.to_account_info(),
+                        mint: ctx.accounts.mint_b.to_account_info(),
+                        to: ctx.accounts.borrower_account_b.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info()
+                    },
+                    signer_seeds
+                ),
+                amount_b,
+                ctx.accounts.mint_b.decimals,
+            )?;
+        }
+
+        emit!(FlashBorrowed {
+            pool: ctx.accounts.pool.key(),
+            borrower: ctx.accounts.borrower.key(),
+            amount_a,
+            amount_b,
+            fee_a: loan.fee_a,
+            fee_b: loan.fee_b,
+        });
+        Ok(())
     }
 
-    msg!("Traded {} tokens ({} after fees) for {}", input, taxed_input, output);
+    pub fn flash_repay(ctx: Context<FlashRepay>) -> Result<()> {
+        let Some(loan) = ctx.accounts.pool.flash_loan else {
+            return err!(TutorialError::NoFlashLoan);
+        };
+        ctx.accounts.pool.update_oracle()?;
+
+        // The pool must receive the loan and its fee net of transfer fees
+        let owed_a = loan.amount_a.checked_add(loan.fee_a).ok_or(TutorialError::MathOverflow)?;
+        let owed_b = loan.amount_b.checked_add(loan.fee_b).ok_or(TutorialError::MathOverflow)?;
+        let amount_a = transfer_fee::amount_to_send(&ctx.accounts.mint_a.to_account_info(), owed_a)?;
+        let amount_b = transfer_fee::amount_to_send(&ctx.accounts.mint_b.to_account_info(), owed_b)?;
+
+        if amount_a > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program_a.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.borrower_account_a.to_account_info(),
+                        mint: ctx.accounts.mint_a.to_account_info(),
+                        to: ctx.accounts.pool_account_a.to_account_info(),
+                        authority: ctx.accounts.borrower.to_account_info()
+                    },
+                ),
+                amount_a,
+                ctx.accounts.mint_a.decimals,
+            )?;
+        }
+        if amount_b > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program_b.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.borrower_account_b.to_account_info(),
+                        mint: ctx.accounts.mint_b.to_account_info(),
+                        to: ctx.accounts.pool_account_b.to_account_info(),
+                        authority: ctx.accounts.borrower.to_account_info()
+                    },
+                ),
+                amount_b,
+                ctx.accounts.mint_b.decimals,
+            )?;
+        }
 
-    // verify the invariant still holds
-    // reload accounts because of the CPIs
-    // we tolerate if the new invariant is higher because it means a roundeing error for LPs
-    ctx.accounts.pool_account_a.reload()?;
-    ctx.accounts.pool_account_b.reload()?;
-    if invariant > ctx.accounts.pool_account_a.amount * ctx.accounts.pool_account_a.amount {
-        return err!(TutorialError::InvariantViolated);
+        // The fee joins the reserves, and the pool accounts must back them again
+        ctx.accounts.pool.flash_loan = None;
+        ctx.accounts.pool.credit_reserves(loan.fee_a, loan.fee_b)?;
+        ctx.accounts.pool_account_a.reload()?;
+        ctx.accounts.pool_account_b.reload()?;
+        ctx.accounts.pool.surplus(ctx.accounts.pool_account_a.amount, ctx.accounts.pool_account_b.amount)?;
+
+        emit!(FlashRepaid {
+            pool: ctx.accounts.pool.key(),
+            borrower: ctx.accounts.borrower.key(),
+            amount_a: loan.amount_a,
+            amount_b: loan.amount_b,
+            fee_a: loan.fee_a,
+            fee_b: loan.fee_b,
+        });
+        Ok(())
     }
 
+    pub fn create_observations(ctx: Context<CreateObservations>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.update_oracle()?;
+
+        let mut observations = ctx.accounts.observations.load_init()?;
+        observations.pool = pool.key();
+        observations.record(pool.observation());
         Ok(())
     }
 
@@ -354,63 +1075,171 @@ pub struct CreateAmm<'info> {
 }
 
 #[derive(Accounts)]
-pub struct CreatePool<'info> {
+#[instruction(fee: u16)]
+pub struct UpdateFee<'info> {
     #[account(
+        mut,
         seeds = [amm.id.as_ref()],
-        bump
+        bump,
+        has_one = admin,
+        constraint = fee < 10000 @ TutorialError::InvalidFee,
     )]
-    pub amm: Box<Account<'info, Amm>>,
+    pub amm: Account<'info, Amm>,
 
-    #[account(
-        init,
-        payer = payer,
-        space = Pool::INIT_SPACE,
-        seeds = [amm.key().as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref()],
-        bump
-    )]
-    pub pool: Box<Account<'info, Pool>>,
+    /// the admin of the AMM
+    pub admin: Signer<'info>,
+}
 
-    /// CHECK: Read only authority
+#[derive(Accounts)]
+pub struct UpdateAmm<'info> {
     #[account(
-        seeds = [amm.key().as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), AUTHORITY_SEED],
-        bump
+        mut,
+        seeds = [amm.id.as_ref()],
+        bump,
+        has_one = admin,
     )]
-    pub pool_authority: AccountInfo<'info>,
+    pub amm: Account<'info, Amm>,
 
-    #[account(
+    /// the admin of the AMM
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(protocol_fee: u16)]
+pub struct SetProtocolFee<'info> {
+    #[account(
+        mut,
+        seeds = [amm.id.as_ref()],
+        bump,
+        has_one = admin,
+        constraint = protocol_fee <= 10000 @ TutorialError::InvalidFee,
+    )]
+    pub amm: Account<'info, Amm>,
+
+    /// the admin of the AMM
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [amm.id.as_ref()],
+        bump,
+        has_one = pending_admin,
+    )]
+    pub amm: Account<'info, Amm>,
+
+    /// the admin proposed by the current admin
+    pub pending_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolStatus<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub amm: Account<'info, Amm>,
+
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+        has_one = amm,
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// the admin of the AMM
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreatePool<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump
+    )]
+    pub amm: Box<Account<'info, Amm>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Pool::INIT_SPACE,
+        seeds = [amm.key().as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref()],
+        bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    /// CHECK: Read only authority
+    #[account(
+        seeds = [amm.key().as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), AUTHORITY_SEED],
+        bump
+    )]
+    pub pool_authority: AccountInfo<'info>,
+
+    #[account(
         init,
         payer = payer,
         seeds = [amm.key().as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), LIQUIDITY_SEED],
         bump,
         mint::decimals = 6,
         mint::authority = pool_authority,
+        mint::token_program = token_program,
     )]
-    pub mint_liquidity: Box<Account<'info, Mint>>,
+    pub mint_liquidity: Box<InterfaceAccount<'info, Mint>>,
 
-    pub mint_a: Box<Account<'info, Mint>>,
-    pub mint_b: Box<Account<'info, Mint>>,
+    /// Holds the locked MINIMUM_LIQUIDITY. It is its own authority and the
+    /// program never signs for it, so nothing can move the tokens out.
+    #[account(
+        init,
+        payer = payer,
+        seeds = [amm.key().as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), LOCK_SEED],
+        bump,
+        token::mint = mint_liquidity,
+        token::authority = liquidity_lock,
+        token::token_program = token_program,
+    )]
+    pub liquidity_lock: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    /// Sorts after mint A so each pair of mints has a single pool
+    #[account(
+        mint::token_program = token_program_b,
+        constraint = mint_a.key() < mint_b.key() @ TutorialError::InvalidMint,
+    )]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
         init,
         payer = payer,
         associated_token::mint = mint_a,
         associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
     )]
-    pub pool_account_a: Box<Account<'info, TokenAccount>>,
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         init,
         payer = payer,
         associated_token::mint = mint_b,
         associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
     )]
-    pub pool_account_b: Box<Account<'info, TokenAccount>>,
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
     /// The account paying for all rents
     #[account(mut)]
     pub payer: Signer<'info>,
 
-    pub token_program: Program<'info, Token>,
+    /// Token program of the liquidity mint
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
@@ -421,8 +1250,15 @@ pub struct DepositLiquidity<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     #[account(
+        seeds = [amm.id.as_ref()],
+        bump
+    )]
+    pub amm: Box<Account<'info, Amm>>,
+    #[account(
+        mut,
         seeds = [pool.amm.as_ref(), pool.mint_a.key().as_ref(), pool.mint_b.key().as_ref()],
         bump,
+        has_one = amm,
         has_one = mint_a,
         has_one = mint_b,
     )]
@@ -442,50 +1278,70 @@ pub struct DepositLiquidity<'info> {
         seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), LIQUIDITY_SEED],
         bump
     )]
-    pub mint_liquidity: Box<Account<'info, Mint>>,
+    pub mint_liquidity: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), LOCK_SEED],
+        bump
+    )]
+    pub liquidity_lock: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
 
-    pub mint_b: Box<Account<'info, Mint>>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
         mut,
         associated_token::mint = mint_a,
         associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
     )]
-    pub pool_account_a: Box<Account<'info, TokenAccount>>,
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
         associated_token::mint = mint_b,
         associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
     )]
-    pub pool_account_b: Box<Account<'info, TokenAccount>>,
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         init_if_needed,
         payer = payer,
         associated_token::mint = mint_liquidity,
         associated_token::authority = depositor,
+        associated_token::token_program = token_program,
     )]
-    pub depositor_account_liquidity: Box<Account<'info, TokenAccount>>,
+    pub depositor_account_liquidity: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
         associated_token::mint = mint_a,
         associated_token::authority = depositor,
+        associated_token::token_program = token_program_a,
     )]
-    pub depositor_account_a: Box<Account<'info, TokenAccount>>,
+    pub depositor_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
         associated_token::mint = mint_b,
         associated_token::authority = depositor,
+        associated_token::token_program = token_program_b,
     )]
-    pub depositor_account_b: Box<Account<'info, TokenAccount>>,
+    pub depositor_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
     /// Solana ecosystem accounts
-    pub token_program: Program<'info, Token>,
+    ///
+    /// Token program of the liquidity mint
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
@@ -498,8 +1354,10 @@ pub struct WithdrawLiquidity<'info> {
     )]
     pub amm: Account<'info, Amm>,
     #[account(
+        mut,
         seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
         bump,
+        has_one = amm,
         has_one = mint_a,
         has_one = mint_b
     )]
@@ -519,54 +1377,64 @@ pub struct WithdrawLiquidity<'info> {
         seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), LIQUIDITY_SEED],
         bump,
     )]
-    pub mint_liquidity: Box<Account<'info, Mint>>,
+    pub mint_liquidity: Box<InterfaceAccount<'info, Mint>>,
 
-    #[account(mut)]
-    pub mint_a: Box<Account<'info, Mint>>,
-    #[account(mut)]
-    pub mint_b: Box<Account<'info, Mint>>,
+    #[account(mut, mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mut, mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
         mut,
         associated_token::mint = mint_a,
         associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
     )]
-    pub pool_account_a: Box<Account<'info, TokenAccount>>,
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(
         mut,
         associated_token::mint = mint_b,
         associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
     )]
-    pub pool_account_b: Box<Account<'info, TokenAccount>>,
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
         associated_token::mint = mint_liquidity,
         associated_token::authority = depositor,
+        associated_token::token_program = token_program,
     )]
-    pub depositor_account_liquidity: Box<Account<'info, TokenAccount>>,
+    pub depositor_account_liquidity: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         init_if_needed,
         payer = payer,
         associated_token::mint = mint_a,
-        associated_token::authority = depositor
+        associated_token::authority = depositor,
+        associated_token::token_program = token_program_a
     )]
-    pub depositer_account_a: Box<Account<'info, TokenAccount>>,
+    pub depositer_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         init_if_needed,
         payer = payer,
         associated_token::mint = mint_b,
-        associated_token::authority = depositor
+        associated_token::authority = depositor,
+        associated_token::token_program = token_program_b
     )]
-    pub depositer_account_b: Box<Account<'info, TokenAccount>>,
+    pub depositer_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
     /// The account paying for all rents
     #[account(mut)]
     pub payer: Signer<'info>,
 
-    pub token_program: Program<'info, Token>,
+    /// Token program of the liquidity mint
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
@@ -580,6 +1448,7 @@ pub struct SwapExactTokensForTokens<'info> {
     pub amm: Account<'info, Amm>,
 
     #[account(
+        mut,
         seeds = [pool.amm.as_ref(), pool.mint_a.key().as_ref(), pool.mint_b.key().as_ref()],
         bump,
         has_one = amm,
@@ -602,53 +1471,528 @@ pub struct SwapExactTokensForTokens<'info> {
 
     /// The account doing the swap
     pub trader: Signer<'info>,
-    pub mint_a: Box<Account<'info, Mint>>,
-    pub mint_b: Box<Account<'info, Mint>>,
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
         mut,
         associated_token::mint = mint_a,
-        associated_token::authority = pool_authority
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a
     )]
-    pub pool_account_a: Box<Account<'info, TokenAccount>>,
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
         associated_token::mint = mint_b,
-        associated_token::authority = pool_authority
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b
     )]
-    pub pool_account_b: Box<Account<'info, TokenAccount>>,
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Price history of the pool, if it keeps one
+    #[account(
+        mut,
+        seeds = [pool.key().as_ref(), OBSERVATIONS_SEED],
+        bump,
+    )]
+    pub observations: Option<AccountLoader<'info, Observations>>,
 
     #[account(
         init_if_needed,
         payer = payer,
         associated_token::mint = mint_a,
-        associated_token::authority = trader
+        associated_token::authority = trader,
+        associated_token::token_program = token_program_a
     )]
-    pub trader_account_a: Box<Account<'info, TokenAccount>>,
+    pub trader_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(
         init_if_needed,
         payer = payer,
         associated_token::mint = mint_b,
-        associated_token::authority = trader
+        associated_token::authority = trader,
+        associated_token::token_program = token_program_b
     )]
-    pub trader_account_b: Box<Account<'info, TokenAccount>>,
+    pub trader_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
     /// The account paying fo rall rents
     #[account(mut)]
     pub payer: Signer<'info>,
 
-    pub token_program: Program<'info, Token>,
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts shared by the whole route, the pools of each hop are passed as
+/// remaining accounts, see `route::ACCOUNTS_PER_HOP`
+#[derive(Accounts)]
+pub struct SwapRoute<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump
+    )]
+    pub amm: Account<'info, Amm>,
+
+    /// The account doing the swap
+    pub trader: Signer<'info>,
+
+    /// The mint sold to the first pool
+    #[account(mint::token_program = token_program_in)]
+    pub mint_in: Box<InterfaceAccount<'info, Mint>>,
+    /// The mint bought from the last pool
+    #[account(mint::token_program = token_program_out)]
+    pub mint_out: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_in,
+        associated_token::authority = trader,
+        associated_token::token_program = token_program_in
+    )]
+    pub trader_account_in: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint_out,
+        associated_token::authority = trader,
+        associated_token::token_program = token_program_out
+    )]
+    pub trader_account_out: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The account paying for all rents
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Token program of the mint sold
+    pub token_program_in: Interface<'info, TokenInterface>,
+    /// Token program of the mint bought
+    pub token_program_out: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CollectProtocolFees<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump
+    )]
+    pub amm: Account<'info, Amm>,
+
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+        has_one = amm,
+        has_one = mint_a,
+        has_one = mint_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: Read only authority
+    #[account(
+        seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), AUTHORITY_SEED],
+        bump
+    )]
+    pub pool_authority: AccountInfo<'info>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
+    )]
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
+    )]
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// the owner of the treasury token accounts, chosen by the admin
+    /// CHECK: Only used to derive the treasury token accounts
+    #[account(address = amm.treasury)]
+    pub treasury: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint_a,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program_a,
+    )]
+    pub treasury_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint_b,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program_b,
+    )]
+    pub treasury_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The account paying for all rents
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct Skim<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump
+    )]
+    pub amm: Account<'info, Amm>,
+
+    #[account(
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+        has_one = amm,
+        has_one = mint_a,
+        has_one = mint_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: Read only authority
+    #[account(
+        seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), AUTHORITY_SEED],
+        bump
+    )]
+    pub pool_authority: AccountInfo<'info>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
+    )]
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
+    )]
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Any token account of mint A, chosen by the caller
+    #[account(
+        mut,
+        token::mint = mint_a,
+        token::token_program = token_program_a,
+    )]
+    pub recipient_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Any token account of mint B, chosen by the caller
+    #[account(
+        mut,
+        token::mint = mint_b,
+        token::token_program = token_program_b,
+    )]
+    pub recipient_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SyncPool<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump,
+        has_one = admin,
+    )]
+    pub amm: Account<'info, Amm>,
+
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+        has_one = amm,
+        has_one = mint_a,
+        has_one = mint_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: Read only authority
+    #[account(
+        seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), AUTHORITY_SEED],
+        bump
+    )]
+    pub pool_authority: AccountInfo<'info>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
+    )]
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
+    )]
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// the admin of the AMM
+    pub admin: Signer<'info>,
+
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateOracle<'info> {
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Price history of the pool, if it keeps one
+    #[account(
+        mut,
+        seeds = [pool.key().as_ref(), OBSERVATIONS_SEED],
+        bump,
+    )]
+    pub observations: Option<AccountLoader<'info, Observations>>,
+}
+
+#[derive(Accounts)]
+pub struct Consult<'info> {
+    #[account(
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        seeds = [pool.key().as_ref(), OBSERVATIONS_SEED],
+        bump,
+    )]
+    pub observations: AccountLoader<'info, Observations>,
+}
+
+#[derive(Accounts)]
+pub struct FlashBorrow<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump
+    )]
+    pub amm: Account<'info, Amm>,
+
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+        has_one = amm,
+        has_one = mint_a,
+        has_one = mint_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: Read only authority
+    #[account(
+        seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), AUTHORITY_SEED],
+        bump
+    )]
+    pub pool_authority: AccountInfo<'info>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
+    )]
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
+    )]
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub borrower: Signer<'info>,
+
+    /// Any token account of mint A, chosen by the borrower
+    #[account(
+        mut,
+        token::mint = mint_a,
+        token::token_program = token_program_a,
+    )]
+    pub borrower_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Any token account of mint B, chosen by the borrower
+    #[account(
+        mut,
+        token::mint = mint_b,
+        token::token_program = token_program_b,
+    )]
+    pub borrower_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Instructions sysvar, searched for the matching `flash_repay`
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
+}
+
+/// `pool` must stay the second account, `flash_loan::require_repayment` looks it up by position.
+#[derive(Accounts)]
+pub struct FlashRepay<'info> {
+    #[account(
+        seeds = [amm.id.as_ref()],
+        bump
+    )]
+    pub amm: Account<'info, Amm>,
+
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+        has_one = amm,
+        has_one = mint_a,
+        has_one = mint_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: Read only authority
+    #[account(
+        seeds = [pool.amm.as_ref(), mint_a.key().as_ref(), mint_b.key().as_ref(), AUTHORITY_SEED],
+        bump
+    )]
+    pub pool_authority: AccountInfo<'info>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_a,
+    )]
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool_authority,
+        associated_token::token_program = token_program_b,
+    )]
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Owner of the accounts repaying the loan, not necessarily the borrower
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        token::mint = mint_a,
+        token::token_program = token_program_a,
+    )]
+    pub borrower_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::mint = mint_b,
+        token::token_program = token_program_b,
+    )]
+    pub borrower_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program of mint A, SPL Token or Token-2022
+    pub token_program_a: Interface<'info, TokenInterface>,
+    /// Token program of mint B, SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CreateObservations<'info> {
+    #[account(
+        mut,
+        seeds = [pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump,
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<Observations>(),
+        seeds = [pool.key().as_ref(), OBSERVATIONS_SEED],
+        bump,
+    )]
+    pub observations: AccountLoader<'info, Observations>,
+
+    /// The account paying for the observations rent
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[account]
 #[derive(InitSpace)]
 pub struct Amm {
     pub id: Pubkey,
     pub admin: Pubkey,
-    pub fee: u16
+    pub fee: u16,
+    /// Admin proposed through `propose_admin`, default when none is pending
+    pub pending_admin: Pubkey,
+    pub status: Status,
+    /// Share of `fee` kept for the protocol, in basis points
+    pub protocol_fee: u16,
+    /// Owner of the token accounts receiving protocol fees
+    pub treasury: Pubkey,
 }
 
 #[account]
@@ -656,7 +2000,387 @@ pub struct Amm {
 pub struct Pool {
     pub amm: Pubkey,
     pub mint_a: Pubkey,
-    pub mint_b: Pubkey
+    pub mint_b: Pubkey,
+    pub status: Status,
+    /// Curve the pool prices trades with
+    pub curve_type: CurveType,
+    /// Parameters of `curve_type`, zero-padded, in the layout `CurveType` documents
+    pub curve_params: [u8; CURVE_PARAMS_LEN],
+    /// Decimals of `mint_a`, which curves pricing both tokens alike depend on
+    pub decimals_a: u8,
+    /// Decimals of `mint_b`
+    pub decimals_b: u8,
+    /// Protocol fees held in `pool_account_a` and owed to the treasury
+    pub protocol_fees_a: u64,
+    /// Protocol fees held in `pool_account_b` and owed to the treasury
+    pub protocol_fees_b: u64,
+    /// Tokens of `pool_account_a` belonging to LPs, only moved by the program's own transfers
+    pub reserve_a: u64,
+    /// Tokens of `pool_account_b` belonging to LPs, only moved by the program's own transfers
+    pub reserve_b: u64,
+    /// Sum over every second of the Q64.64 price of token A in token B, wrapping on overflow
+    pub price_a_cumulative: u128,
+    /// Sum over every second of the Q64.64 price of token B in token A, wrapping on overflow
+    pub price_b_cumulative: u128,
+    /// Sum over every second of the geometric mean of the reserves, wrapping on overflow
+    pub liquidity_cumulative: u128,
+    /// Unix timestamp up to which the price accumulators are up to date
+    pub last_update: i64,
+    /// Loan taken by `flash_borrow` and not yet repaid, which locks the pool
+    pub flash_loan: Option<FlashLoan>,
+}
+
+impl Pool {
+    /// Reserves belonging to LPs, which every price is computed from.
+    pub fn reserves(&self) -> (u64, u64) {
+        (self.reserve_a, self.reserve_b)
+    }
+
+    /// Curve the pool prices trades with.
+    pub fn curve(&self) -> Result<Box<dyn Curve>> {
+        self.curve_type.load(&self.curve_params, self.decimals_a, self.decimals_b)
+    }
+
+    /// Taxed input and output of a swap of `input` tokens on the pool's curve, A for B if `swap_a`.
+    pub fn swap_output(&self, swap_a: bool, input: u64, fee: u16) -> Result<(u64, u64)> {
+        let (reserve_a, reserve_b) = self.reserves();
+        self.curve()?.swap_out(swap_a, input, fee, reserve_a, reserve_b)
+    }
+
+    /// Input and taxed input of a swap paying out exactly `output` tokens on
+    /// the pool's curve, A for B if `swap_a`.
+    pub fn swap_input(&self, swap_a: bool, output: u64, fee: u16) -> Result<(u64, u64)> {
+        let (reserve_a, reserve_b) = self.reserves();
+        self.curve()?.swap_in(swap_a, output, fee, reserve_a, reserve_b)
+    }
+
+    /// Value of the reserves that no trade may decrease.
+    pub fn invariant(&self) -> Result<u128> {
+        let (reserve_a, reserve_b) = self.reserves();
+        self.curve()?.invariant(reserve_a, reserve_b)
+    }
+
+    /// Fails while a flash loan is open, so the pool cannot be used before it is repaid.
+    pub fn require_unlocked(&self) -> Result<()> {
+        if self.flash_loan.is_some() {
+            return err!(TutorialError::FlashLoanOpen);
+        }
+        Ok(())
+    }
+
+    /// Accumulates the current price and liquidity up to now. Called before the reserves change.
+    pub fn update_oracle(&mut self) -> Result<()> {
+        let observation = self.observe(Clock::get()?.unix_timestamp)?;
+        self.price_a_cumulative = observation.price_a_cumulative;
+        self.price_b_cumulative = observation.price_b_cumulative;
+        self.liquidity_cumulative = observation.liquidity_cumulative;
+        self.last_update = observation.timestamp;
+        Ok(())
+    }
+
+    /// Accumulators as of the last update.
+    pub fn observation(&self) -> Observation {
+        Observation {
+            price_a_cumulative: self.price_a_cumulative,
+            price_b_cumulative: self.price_b_cumulative,
+            liquidity_cumulative: self.liquidity_cumulative,
+            timestamp: self.last_update,
+            padding: [0; 8],
+        }
+    }
+
+    /// Accumulators at `timestamp` if the reserves do not change until then.
+    pub fn observe(&self, timestamp: i64) -> Result<Observation> {
+        let elapsed = timestamp.saturating_sub(self.last_update).max(0) as u64;
+        let curve = self.curve()?;
+        let (price_a_cumulative, price_b_cumulative) = oracle::accumulate_prices(
+            self.price_a_cumulative,
+            self.price_b_cumulative,
+            curve.spot_price(true, self.reserve_a, self.reserve_b),
+            curve.spot_price(false, self.reserve_a, self.reserve_b),
+            elapsed,
+        );
+        Ok(Observation {
+            price_a_cumulative,
+            price_b_cumulative,
+            liquidity_cumulative: oracle::accumulate_liquidity(
+                self.liquidity_cumulative,
+                self.reserve_a,
+                self.reserve_b,
+                elapsed,
+            ),
+            timestamp: self.last_update.max(timestamp),
+            padding: [0; 8],
+        })
+    }
+
+    /// Adds tokens received by the pool to its reserves.
+    pub fn credit_reserves(&mut self, amount_a: u64, amount_b: u64) -> Result<()> {
+        self.reserve_a = self.reserve_a.checked_add(amount_a).ok_or(TutorialError::MathOverflow)?;
+        self.reserve_b = self.reserve_b.checked_add(amount_b).ok_or(TutorialError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Removes tokens sent by the pool from its reserves.
+    pub fn debit_reserves(&mut self, amount_a: u64, amount_b: u64) -> Result<()> {
+        self.reserve_a = self.reserve_a.checked_sub(amount_a).ok_or(TutorialError::MathOverflow)?;
+        self.reserve_b = self.reserve_b.checked_sub(amount_b).ok_or(TutorialError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Records a swap that paid `input` into the pool and `output` out of it.
+    ///
+    /// `protocol_fee` of the input is owed to the treasury, the rest joins the reserves.
+    pub fn record_swap(&mut self, swap_a: bool, input: u64, output: u64, protocol_fee: u64) -> Result<()> {
+        let (reserve_in, fees_in, reserve_out) = if swap_a {
+            (&mut self.reserve_a, &mut self.protocol_fees_a, &mut self.reserve_b)
+        } else {
+            (&mut self.reserve_b, &mut self.protocol_fees_b, &mut self.reserve_a)
+        };
+        *fees_in = fees_in.checked_add(protocol_fee).ok_or(TutorialError::MathOverflow)?;
+        *reserve_in = reserve_in
+            .checked_add(input.checked_sub(protocol_fee).ok_or(TutorialError::MathOverflow)?)
+            .ok_or(TutorialError::MathOverflow)?;
+        *reserve_out = reserve_out.checked_sub(output).ok_or(TutorialError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Balances of the pool token accounts beyond the reserves and protocol fees,
+    /// such as tokens transferred to them directly.
+    ///
+    /// Fails if the balances no longer cover the reserves and protocol fees.
+    pub fn surplus(&self, balance_a: u64, balance_b: u64) -> Result<(u64, u64)> {
+        let surplus = |balance: u64, reserve: u64, fees: u64| {
+            reserve
+                .checked_add(fees)
+                .and_then(|owed| balance.checked_sub(owed))
+                .ok_or_else(|| error!(TutorialError::InvariantViolated))
+        };
+        Ok((
+            surplus(balance_a, self.reserve_a, self.protocol_fees_a)?,
+            surplus(balance_b, self.reserve_b, self.protocol_fees_b)?,
+        ))
+    }
+}
+
+/// Tokens lent by a pool within a transaction, owed back with their fee.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct FlashLoan {
+    pub amount_a: u64,
+    pub amount_b: u64,
+    /// Fee owed on top of `amount_a`, paid to the LPs
+    pub fee_a: u64,
+    /// Fee owed on top of `amount_b`, paid to the LPs
+    pub fee_b: u64,
+}
+
+/// Trading mode of an AMM or a pool.
+///
+/// A pool runs under the stricter of its own status and its AMM's status.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, InitSpace)]
+pub enum Status {
+    /// Swaps, deposits and withdrawals are allowed
+    Active,
+    /// Only `withdraw_liquidity` is allowed
+    WithdrawOnly,
+    /// Nothing is allowed
+    Frozen,
+}
+
+impl Status {
+    /// Fails unless swaps and deposits are allowed.
+    pub fn require_trading(self) -> Result<()> {
+        match self {
+            Status::Active => Ok(()),
+            Status::WithdrawOnly => err!(TutorialError::WithdrawOnly),
+            Status::Frozen => err!(TutorialError::Frozen),
+        }
+    }
+
+    /// Fails unless withdrawals are allowed.
+    pub fn require_withdrawals(self) -> Result<()> {
+        match self {
+            Status::Active | Status::WithdrawOnly => Ok(()),
+            Status::Frozen => err!(TutorialError::Frozen),
+        }
+    }
+}
+
+/// Last moment at which a transaction may still execute, inclusive.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deadline {
+    UnixTimestamp(i64),
+    Slot(u64),
+}
+
+impl Deadline {
+    /// Fails if `deadline` is set and has already passed.
+    pub fn require_pending(deadline: Option<Deadline>) -> Result<()> {
+        let Some(deadline) = deadline else {
+            return Ok(());
+        };
+        let clock = Clock::get()?;
+        let expired = match deadline {
+            Deadline::UnixTimestamp(timestamp) => clock.unix_timestamp > timestamp,
+            Deadline::Slot(slot) => clock.slot > slot,
+        };
+        if expired {
+            return err!(TutorialError::Expired);
+        }
+        Ok(())
+    }
+}
+
+#[event]
+pub struct AmmCreated {
+    pub amm: Pubkey,
+    pub id: Pubkey,
+    pub admin: Pubkey,
+    pub fee: u16,
+}
+
+/// Configuration of an AMM after an admin instruction
+#[event]
+pub struct AmmUpdated {
+    pub amm: Pubkey,
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub fee: u16,
+    pub protocol_fee: u16,
+    pub treasury: Pubkey,
+    pub status: Status,
+}
+
+impl AmmUpdated {
+    pub fn new(key: Pubkey, amm: &Amm) -> Self {
+        Self {
+            amm: key,
+            admin: amm.admin,
+            pending_admin: amm.pending_admin,
+            fee: amm.fee,
+            protocol_fee: amm.protocol_fee,
+            treasury: amm.treasury,
+            status: amm.status,
+        }
+    }
+}
+
+#[event]
+pub struct PoolCreated {
+    pub amm: Pubkey,
+    pub pool: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub mint_liquidity: Pubkey,
+    pub curve_type: CurveType,
+    pub curve_params: [u8; CURVE_PARAMS_LEN],
+}
+
+#[event]
+pub struct PoolStatusUpdated {
+    pub pool: Pubkey,
+    pub status: Status,
+}
+
+/// Amounts are what the pool received, net of Token-2022 transfer fees.
+/// Reserves are the LP reserves after the deposit
+#[event]
+pub struct LiquidityDeposited {
+    pub pool: Pubkey,
+    pub depositor: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    /// Liquidity tokens minted to the depositor
+    pub liquidity: u64,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+/// Reserves are the LP reserves after the withdrawal
+#[event]
+pub struct LiquidityWithdrawn {
+    pub pool: Pubkey,
+    pub depositor: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    /// Liquidity tokens burned from the depositor
+    pub liquidity: u64,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+/// Amounts are what the pool received and sent, so transfer fees withheld
+/// from the trader are not included.
+/// Reserves are the LP reserves after the swap
+#[event]
+pub struct Swapped {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    /// Whether token A was sold for token B
+    pub swap_a: bool,
+    pub input: u64,
+    pub output: u64,
+    /// Trading fee charged on the input, protocol share included
+    pub fee: u64,
+    pub protocol_fee: u64,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct ProtocolFeesCollected {
+    pub pool: Pubkey,
+    pub treasury: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct Skimmed {
+    pub pool: Pubkey,
+    pub recipient_account_a: Pubkey,
+    pub recipient_account_b: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+/// Surplus adopted into the reserves by `sync`
+#[event]
+pub struct Synced {
+    pub pool: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct FlashBorrowed {
+    pub pool: Pubkey,
+    pub borrower: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub fee_a: u64,
+    pub fee_b: u64,
+}
+
+#[event]
+pub struct FlashRepaid {
+    pub pool: Pubkey,
+    pub borrower: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub fee_a: u64,
+    pub fee_b: u64,
+}
+
+#[event]
+pub struct OracleUpdated {
+    pub pool: Pubkey,
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+    pub liquidity_cumulative: u128,
+    pub last_update: i64,
 }
 
 #[error_code]
@@ -671,4 +2395,40 @@ pub enum TutorialError {
     OutputTooSmall,
     #[msg("Invariant does not hold")]
     InvariantViolated,
+    #[msg("Arithmetic overflow in pool math")]
+    MathOverflow,
+    #[msg("Input is above the maximum expected")]
+    InputTooLarge,
+    #[msg("Pool reserves cannot cover the requested output")]
+    InsufficientLiquidity,
+    #[msg("Only withdrawals are allowed")]
+    WithdrawOnly,
+    #[msg("Trading is frozen")]
+    Frozen,
+    #[msg("Invalid swap route")]
+    InvalidRoute,
+    #[msg("Liquidity minted is below the minimum expected")]
+    LiquidityTooSmall,
+    #[msg("Withdrawn amounts are below the minimum expected")]
+    WithdrawalTooSmall,
+    #[msg("Transaction deadline has passed")]
+    Expired,
+    #[msg("Observations do not reach that far back")]
+    ObservationTooOld,
+    #[msg("Flash loan is not repaid later in the transaction")]
+    FlashLoanNotRepaid,
+    #[msg("Pool is locked by an open flash loan")]
+    FlashLoanOpen,
+    #[msg("Pool has no flash loan to repay")]
+    NoFlashLoan,
+    #[msg("Amplification must be between 1 and 1000000")]
+    InvalidAmplification,
+    #[msg("Invariant solver did not converge")]
+    NoConvergence,
+    #[msg("Weights must be at least 100 basis points each and add up to 10000")]
+    InvalidWeights,
+    #[msg("Curve parameters do not match the curve type")]
+    InvalidCurveParams,
+    #[msg("Mint decimals are too far apart for the curve")]
+    DecimalsGapTooLarge,
 }
\ No newline at end of file
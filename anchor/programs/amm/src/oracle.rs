@@ -0,0 +1,283 @@
+//! Time-weighted average prices, in the style of Uniswap v2.
+//!
+//! Every pool adds its spot prices, weighted by the seconds they were in
+//! effect, to a pair of accumulators. Reading an accumulator at two moments
+//! and dividing the difference by the elapsed time gives the average price in
+//! between, which a single transaction cannot move much.
+//!
+//! Prices are Q64.64 fixed-point numbers and accumulators wrap on overflow,
+//! so differences must be taken with `wrapping_sub` as `average_price` does.
+//!
+//! Pools may also keep an `Observations` account, a ring buffer of past
+//! accumulator values written on swaps, so readers can take a TWAP over any
+//! window it covers without storing checkpoints of their own.
+
+use anchor_lang::prelude::*;
+
+use crate::{curve, Pool, TutorialError};
+
+/// Entries kept by an `Observations` account before the oldest is overwritten.
+pub const OBSERVATION_SLOTS: usize = 128;
+
+/// Fractional bits of the Q64.64 prices.
+pub const PRICE_FRACTIONAL_BITS: u32 = 64;
+
+/// Price of one unit of the base token in units of the quote token, as a
+/// Q64.64 number. `None` when there is no base reserve to price against.
+pub fn spot_price(reserve_base: u64, reserve_quote: u64) -> Option<u128> {
+    ((reserve_quote as u128) << PRICE_FRACTIONAL_BITS).checked_div(reserve_base as u128)
+}
+
+/// Accumulators after the spot prices stayed at `price_a` and `price_b` for
+/// `elapsed` seconds, untouched unless both prices are known.
+pub fn accumulate_prices(
+    price_a_cumulative: u128,
+    price_b_cumulative: u128,
+    price_a: Option<u128>,
+    price_b: Option<u128>,
+    elapsed: u64,
+) -> (u128, u128) {
+    match (price_a, price_b) {
+        (Some(price_a), Some(price_b)) => (
+            price_a_cumulative.wrapping_add(price_a.wrapping_mul(elapsed as u128)),
+            price_b_cumulative.wrapping_add(price_b.wrapping_mul(elapsed as u128)),
+        ),
+        _ => (price_a_cumulative, price_b_cumulative),
+    }
+}
+
+/// Liquidity accumulator after the reserves stayed unchanged for `elapsed`
+/// seconds, adding the geometric mean of the reserves every second.
+pub fn accumulate_liquidity(liquidity_cumulative: u128, reserve_a: u64, reserve_b: u64, elapsed: u64) -> u128 {
+    let liquidity = curve::sqrt(reserve_a as u128 * reserve_b as u128);
+    liquidity_cumulative.wrapping_add(liquidity as u128 * elapsed as u128)
+}
+
+/// Average Q64.64 price between two readings of an accumulator taken
+/// `elapsed` seconds apart.
+pub fn average_price(cumulative_start: u128, cumulative_end: u128, elapsed: u64) -> Option<u128> {
+    cumulative_end.wrapping_sub(cumulative_start).checked_div(elapsed as u128)
+}
+
+/// Accumulators of a pool at a point in time.
+///
+/// Borsh is derived directly so `consult` can return it, as `AnchorSerialize`
+/// would repeat the IDL type `zero_copy` already builds.
+#[zero_copy]
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize, Default, Debug, PartialEq)]
+pub struct Observation {
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+    pub liquidity_cumulative: u128,
+    pub timestamp: i64,
+    pub padding: [u8; 8],
+}
+
+impl Observation {
+    /// Accumulators at `timestamp`, between `before` and `after`, assuming the
+    /// price and liquidity were constant in between.
+    fn interpolate(before: &Self, after: &Self, timestamp: i64) -> Result<Self> {
+        if timestamp == before.timestamp {
+            return Ok(*before);
+        }
+        let span = (after.timestamp - before.timestamp) as u128;
+        let offset = (timestamp - before.timestamp) as u128;
+        // Multiplying first keeps the remainder of the delta over the span
+        let lerp = |start: u128, end: u128| -> Result<u128> {
+            Ok(start.wrapping_add(curve::mul_div(end.wrapping_sub(start), offset, span)?))
+        };
+        Ok(Self {
+            price_a_cumulative: lerp(before.price_a_cumulative, after.price_a_cumulative)?,
+            price_b_cumulative: lerp(before.price_b_cumulative, after.price_b_cumulative)?,
+            liquidity_cumulative: lerp(before.liquidity_cumulative, after.liquidity_cumulative)?,
+            timestamp,
+            padding: [0; 8],
+        })
+    }
+}
+
+/// Past accumulator values of a pool, at PDA `[pool, OBSERVATIONS_SEED]`.
+#[account(zero_copy)]
+pub struct Observations {
+    pub pool: Pubkey,
+    /// Slot of the newest entry
+    pub index: u32,
+    /// Slots written so far, up to `OBSERVATION_SLOTS`
+    pub len: u32,
+    pub padding: [u8; 8],
+    pub observations: [Observation; OBSERVATION_SLOTS],
+}
+
+impl Observations {
+    /// Appends `observation`, overwriting the oldest entry once the buffer is
+    /// full. At most one entry is kept per timestamp.
+    pub fn record(&mut self, observation: Observation) {
+        if self.len > 0 {
+            if self.observations[self.index as usize].timestamp >= observation.timestamp {
+                return;
+            }
+            self.index = (self.index + 1) % OBSERVATION_SLOTS as u32;
+        }
+        self.len = (self.len + 1).min(OBSERVATION_SLOTS as u32);
+        self.observations[self.index as usize] = observation;
+    }
+
+    /// Accumulators of `pool` `seconds_ago` seconds before now.
+    ///
+    /// The TWAP over the last `window` seconds is the difference between
+    /// `consult(pool, 0)` and `consult(pool, window)` divided by `window`.
+    pub fn consult(&self, pool: &Account<Pool>, seconds_ago: u32) -> Result<Observation> {
+        require_keys_eq!(self.pool, pool.key(), ErrorCode::ConstraintHasOne);
+        self.observe(pool.observe(Clock::get()?.unix_timestamp)?, seconds_ago)
+    }
+
+    /// Accumulators `seconds_ago` seconds before `latest`, interpolated
+    /// between the entries around that time.
+    pub fn observe(&self, latest: Observation, seconds_ago: u32) -> Result<Observation> {
+        let timestamp = latest.timestamp - seconds_ago as i64;
+        let mut after = latest;
+        for age in 0..self.len as usize {
+            let before = &self.observations[(self.index as usize + OBSERVATION_SLOTS - age) % OBSERVATION_SLOTS];
+            if before.timestamp <= timestamp {
+                return Observation::interpolate(before, &after, timestamp);
+            }
+            after = *before;
+        }
+        if timestamp == after.timestamp {
+            return Ok(after);
+        }
+        err!(TutorialError::ObservationTooOld)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: u128 = 1 << PRICE_FRACTIONAL_BITS;
+
+    #[test]
+    fn spot_price_is_the_reserve_ratio() {
+        assert_eq!(spot_price(100, 200), Some(2 * ONE));
+        assert_eq!(spot_price(200, 100), Some(ONE / 2));
+        assert_eq!(spot_price(1, u64::MAX), Some((u64::MAX as u128) << 64));
+        assert_eq!(spot_price(0, 100), None);
+    }
+
+    #[test]
+    fn twap_weights_prices_by_time() {
+        // 2 B per A for 30 seconds, then 4 B per A for 10 seconds
+        let (a, b) = accumulate_prices(0, 0, Some(2 * ONE), Some(ONE / 2), 30);
+        let (a, b) = accumulate_prices(a, b, Some(4 * ONE), Some(ONE / 4), 10);
+
+        assert_eq!(average_price(0, a, 40), Some(ONE * 5 / 2));
+        assert_eq!(average_price(0, b, 40), Some(ONE * 7 / 16));
+    }
+
+    #[test]
+    fn twap_survives_accumulator_overflow() {
+        let start = u128::MAX - ONE;
+        let (end, _) = accumulate_prices(start, 0, Some(3 * ONE), Some(ONE / 3), 100);
+        assert!(end < start);
+        assert_eq!(average_price(start, end, 100), Some(3 * ONE));
+    }
+
+    #[test]
+    fn empty_pools_do_not_accumulate() {
+        assert_eq!(accumulate_prices(7, 9, None, None, 100), (7, 9));
+        assert_eq!(accumulate_prices(7, 9, spot_price(0, 100), spot_price(100, 0), 100), (7, 9));
+        assert_eq!(average_price(7, 7, 0), None);
+    }
+
+    fn observation(timestamp: i64, price_a: u128, liquidity: u128) -> Observation {
+        Observation {
+            price_a_cumulative: price_a,
+            liquidity_cumulative: liquidity,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    fn observations() -> Box<Observations> {
+        Box::new(Observations {
+            pool: Pubkey::default(),
+            index: 0,
+            len: 0,
+            padding: [0; 8],
+            observations: [Observation::default(); OBSERVATION_SLOTS],
+        })
+    }
+
+    #[test]
+    fn liquidity_accumulates_the_geometric_mean_of_the_reserves() {
+        assert_eq!(accumulate_liquidity(5, 100, 400, 10), 5 + 200 * 10);
+        assert_eq!(accumulate_liquidity(0, 0, 400, 10), 0);
+    }
+
+    #[test]
+    fn observations_keep_one_entry_per_timestamp() {
+        let mut buffer = observations();
+        buffer.record(observation(10, 1, 1));
+        buffer.record(observation(10, 2, 2));
+        buffer.record(observation(9, 3, 3));
+        assert_eq!(buffer.len, 1);
+        assert_eq!(buffer.observations[0], observation(10, 1, 1));
+    }
+
+    #[test]
+    fn observations_overwrite_the_oldest_entry_when_full() {
+        let mut buffer = observations();
+        for timestamp in 0..OBSERVATION_SLOTS as i64 + 3 {
+            buffer.record(observation(timestamp, timestamp as u128, 0));
+        }
+        assert_eq!(buffer.len as usize, OBSERVATION_SLOTS);
+        assert_eq!(buffer.index, 2);
+        assert_eq!(buffer.observations[2].timestamp, OBSERVATION_SLOTS as i64 + 2);
+        assert_eq!(buffer.observations[3].timestamp, 3);
+    }
+
+    #[test]
+    fn consult_interpolates_between_entries() {
+        let mut buffer = observations();
+        // 2.0 per second from 100 to 110, then 4.0 per second up to 120
+        buffer.record(observation(100, 1000 * ONE, 50));
+        buffer.record(observation(110, 1020 * ONE, 150));
+        let latest = observation(120, 1060 * ONE, 250);
+
+        assert_eq!(buffer.observe(latest, 0).unwrap(), latest);
+        assert_eq!(buffer.observe(latest, 10).unwrap(), observation(110, 1020 * ONE, 150));
+        assert_eq!(buffer.observe(latest, 5).unwrap(), observation(115, 1040 * ONE, 200));
+        assert_eq!(buffer.observe(latest, 15).unwrap(), observation(105, 1010 * ONE, 100));
+        assert_eq!(buffer.observe(latest, 20).unwrap(), observation(100, 1000 * ONE, 50));
+
+        // Average price over the last 15 seconds
+        let start = buffer.observe(latest, 15).unwrap();
+        assert_eq!(
+            average_price(start.price_a_cumulative, latest.price_a_cumulative, 15),
+            Some(ONE * 10 / 3)
+        );
+    }
+
+    #[test]
+    fn consult_interpolates_deltas_that_do_not_divide_by_the_span() {
+        const THIRD: u128 = u128::MAX / 3;
+        let mut buffer = observations();
+        // Deltas one short of a multiple of the 3 second span, one overflowing 128 bits once multiplied
+        buffer.record(observation(100, 0, 0));
+        let latest = observation(103, u128::MAX - 1, 2);
+
+        assert_eq!(buffer.observe(latest, 2).unwrap(), observation(101, THIRD - 1, 0));
+        assert_eq!(buffer.observe(latest, 1).unwrap(), observation(102, 2 * THIRD - 1, 1));
+    }
+
+    #[test]
+    fn consult_rejects_windows_older_than_the_buffer() {
+        let mut buffer = observations();
+        let latest = observation(120, 0, 0);
+        assert_eq!(buffer.observe(latest, 0).unwrap(), latest);
+        assert_eq!(buffer.observe(latest, 1).unwrap_err(), TutorialError::ObservationTooOld.into());
+
+        buffer.record(observation(100, 0, 0));
+        assert_eq!(buffer.observe(latest, 21).unwrap_err(), TutorialError::ObservationTooOld.into());
+    }
+}
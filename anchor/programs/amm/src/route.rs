@@ -0,0 +1,146 @@
+//! Pools visited by `swap_route`, loaded from the instruction's remaining accounts.
+//!
+//! Anchor cannot validate a variable number of pools through `#[derive(Accounts)]`,
+//! so every hop repeats the checks `SwapExactTokensForTokens` declares with
+//! `seeds` and `has_one`.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::get_associated_token_address_with_program_id,
+    token_interface::{Mint, TokenAccount, TokenInterface},
+};
+
+use crate::{oracle::Observations, Amm, Pool, TutorialError, AUTHORITY_SEED, OBSERVATIONS_SEED};
+
+/// Remaining accounts taken by each hop, in order: pool, pool authority,
+/// mint A, mint B, pool account A, pool account B, token program A,
+/// token program B and observations.
+///
+/// As for optional accounts of `#[derive(Accounts)]`, the program id stands
+/// in for the observations of a pool that keeps none.
+pub const ACCOUNTS_PER_HOP: usize = 9;
+
+/// Amounts traded in one hop, priced on the reserves before any transfer of the route.
+pub struct Quote {
+    pub swap_a: bool,
+    /// Amount received by the pool, net of transfer fees
+    pub input: u64,
+    pub taxed_input: u64,
+    /// Amount sent by the pool, before transfer fees
+    pub output: u64,
+    pub protocol_fee: u64,
+    /// Invariant of the pool's curve before the trade
+    pub invariant: u128,
+}
+
+/// One pool of a route with the accounts needed to trade against it.
+pub struct Hop<'info> {
+    pub pool: Box<Account<'info, Pool>>,
+    pub pool_authority: AccountInfo<'info>,
+    pub authority_bump: u8,
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub pool_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub pool_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    /// Price history of the pool, if it keeps one
+    pub observations: Option<AccountLoader<'info, Observations>>,
+}
+
+impl<'info> Hop<'info> {
+    /// Loads and validates a hop of `amm` from its `ACCOUNTS_PER_HOP` accounts.
+    pub fn load(amm: &Account<'info, Amm>, accounts: &'info [AccountInfo<'info>]) -> Result<Self> {
+        let [pool, pool_authority, mint_a, mint_b, pool_account_a, pool_account_b, token_program_a, token_program_b, observations] =
+            accounts
+        else {
+            return err!(TutorialError::InvalidRoute);
+        };
+
+        let pool = Box::new(Account::<Pool>::try_from(pool)?);
+        require_keys_eq!(pool.amm, amm.key(), ErrorCode::ConstraintHasOne);
+        require_keys_eq!(pool.mint_a, mint_a.key(), ErrorCode::ConstraintHasOne);
+        require_keys_eq!(pool.mint_b, mint_b.key(), ErrorCode::ConstraintHasOne);
+
+        let (pool_key, _) = Pubkey::find_program_address(
+            &[pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+            &crate::ID,
+        );
+        require_keys_eq!(pool_key, pool.key(), ErrorCode::ConstraintSeeds);
+        let (authority_key, authority_bump) = Pubkey::find_program_address(
+            &[pool.amm.as_ref(), pool.mint_a.as_ref(), pool.mint_b.as_ref(), AUTHORITY_SEED],
+            &crate::ID,
+        );
+        require_keys_eq!(authority_key, pool_authority.key(), ErrorCode::ConstraintSeeds);
+
+        let token_program_a = Interface::<TokenInterface>::try_from(token_program_a)?;
+        let token_program_b = Interface::<TokenInterface>::try_from(token_program_b)?;
+        require_keys_eq!(*mint_a.owner, token_program_a.key(), ErrorCode::ConstraintMintTokenProgram);
+        require_keys_eq!(*mint_b.owner, token_program_b.key(), ErrorCode::ConstraintMintTokenProgram);
+
+        require_keys_eq!(
+            pool_account_a.key(),
+            get_associated_token_address_with_program_id(&authority_key, &pool.mint_a, &token_program_a.key()),
+            ErrorCode::ConstraintAssociated
+        );
+        require_keys_eq!(
+            pool_account_b.key(),
+            get_associated_token_address_with_program_id(&authority_key, &pool.mint_b, &token_program_b.key()),
+            ErrorCode::ConstraintAssociated
+        );
+        if !pool.to_account_info().is_writable || !pool_account_a.is_writable || !pool_account_b.is_writable {
+            return err!(ErrorCode::ConstraintMut);
+        }
+
+        let observations = if observations.key() == crate::ID {
+            None
+        } else {
+            let (observations_key, _) = Pubkey::find_program_address(&[pool.key().as_ref(), OBSERVATIONS_SEED], &crate::ID);
+            require_keys_eq!(observations_key, observations.key(), ErrorCode::ConstraintSeeds);
+            if !observations.is_writable {
+                return err!(ErrorCode::ConstraintMut);
+            }
+            Some(AccountLoader::try_from(observations)?)
+        };
+
+        Ok(Self {
+            pool,
+            pool_authority: pool_authority.clone(),
+            authority_bump,
+            mint_a: Box::new(InterfaceAccount::try_from(mint_a)?),
+            mint_b: Box::new(InterfaceAccount::try_from(mint_b)?),
+            pool_account_a: Box::new(InterfaceAccount::try_from(pool_account_a)?),
+            pool_account_b: Box::new(InterfaceAccount::try_from(pool_account_b)?),
+            token_program_a,
+            token_program_b,
+            observations,
+        })
+    }
+
+    /// Whether this hop sells token A, given the mint entering the pool.
+    pub fn swap_a(&self, mint_in: &Pubkey) -> Result<bool> {
+        if *mint_in == self.pool.mint_a {
+            Ok(true)
+        } else if *mint_in == self.pool.mint_b {
+            Ok(false)
+        } else {
+            err!(TutorialError::InvalidRoute)
+        }
+    }
+
+    /// Mint, pool account and token program on the given side of the pool.
+    pub fn side(
+        &self,
+        a: bool,
+    ) -> (
+        &InterfaceAccount<'info, Mint>,
+        &InterfaceAccount<'info, TokenAccount>,
+        &Interface<'info, TokenInterface>,
+    ) {
+        if a {
+            (&self.mint_a, &self.pool_account_a, &self.token_program_a)
+        } else {
+            (&self.mint_b, &self.pool_account_b, &self.token_program_b)
+        }
+    }
+}
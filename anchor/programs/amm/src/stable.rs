@@ -0,0 +1,542 @@
+//! StableSwap pricing for pegged pairs, after Curve's invariant for two tokens:
+//!
+//! ```text
+//! 4A(x + y) + D = 4AD + D³ / 4xy
+//! ```
+//!
+//! The amplification coefficient `A` flattens the curve around the peg, so
+//! trades between balanced reserves pay almost no slippage, and the curve
+//! falls back towards constant product as the reserves drift apart. Both
+//! reserves are scaled to the larger of the two mint decimals before entering
+//! the invariant, so one unit of either token weighs the same.
+//!
+//! Neither `D` nor a reserve solving the invariant for a given `D` has a
+//! closed form. Both are found with Newton's method in integer arithmetic,
+//! carrying 256-bit intermediate products, and as in `curve` every result is
+//! rounded in favour of the pool.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    curve::{
+        self, checked, div_ceil, div_floor, mul_div, mul_div_ceil, to_amount, Curve,
+        FEE_DENOMINATOR,
+    },
+    TutorialError, MINIMUM_LIQUIDITY,
+};
+
+/// Largest accepted amplification coefficient.
+pub const MAX_AMPLIFICATION: u64 = 1_000_000;
+
+/// Newton steps after which the solvers give up.
+const MAX_ITERATIONS: usize = 255;
+
+/// StableSwap curve of one pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StableCurve {
+    /// `A · 2²`, the coefficient of the sum in the invariant
+    ann: u128,
+    /// Multiplier bringing token A to the common decimals
+    rate_a: u128,
+    /// Multiplier bringing token B to the common decimals
+    rate_b: u128,
+}
+
+impl StableCurve {
+    /// Curve with amplification `amplification` between mints of the given decimals.
+    pub fn new(amplification: u64, decimals_a: u8, decimals_b: u8) -> Result<Self> {
+        if amplification == 0 || amplification > MAX_AMPLIFICATION {
+            return err!(TutorialError::InvalidAmplification);
+        }
+        let decimals = decimals_a.max(decimals_b);
+        let rate =
+            |decimals_token: u8| checked(10u128.checked_pow((decimals - decimals_token) as u32));
+        let (rate_a, rate_b) = (rate(decimals_a)?, rate(decimals_b)?);
+        // Scaled reserves must leave room for the products in the solvers
+        if rate_a.max(rate_b) > 1 << 32 {
+            return err!(TutorialError::DecimalsGapTooLarge);
+        }
+        Ok(Self {
+            ann: amplification as u128 * 4,
+            rate_a,
+            rate_b,
+        })
+    }
+
+    /// Reserves in common decimals.
+    fn scale(&self, reserve_a: u64, reserve_b: u64) -> (u128, u128) {
+        (
+            reserve_a as u128 * self.rate_a,
+            reserve_b as u128 * self.rate_b,
+        )
+    }
+
+    /// Rates of the token going into and out of the pool.
+    fn rates(&self, swap_a: bool) -> (u128, u128) {
+        if swap_a {
+            (self.rate_a, self.rate_b)
+        } else {
+            (self.rate_b, self.rate_a)
+        }
+    }
+
+    /// `D` for the given reserves in common decimals, zero if either is empty.
+    fn d(&self, x: u128, y: u128) -> Result<u128> {
+        if x == 0 || y == 0 {
+            return Ok(0);
+        }
+        let sum = x + y;
+        let (low, high) = (x.min(y), x.max(y));
+        let mut d = sum;
+        for _ in 0..MAX_ITERATIONS {
+            // D³ / 4xy, dividing by the smaller reserve first so the larger
+            // one keeps the rounding error from growing between iterations
+            let d_product = mul_div(mul_div(d, d, low * 2)?, d, high * 2)?;
+            let previous = d;
+            let numerator = checked(
+                self.ann
+                    .checked_mul(sum)
+                    .and_then(|value| value.checked_add(d_product.checked_mul(2)?)),
+            )?;
+            let denominator = checked(
+                (self.ann - 1)
+                    .checked_mul(d)
+                    .and_then(|value| value.checked_add(d_product.checked_mul(3)?)),
+            )?;
+            d = mul_div(numerator, d, denominator)?;
+            if d.abs_diff(previous) <= 1 {
+                return Ok(d);
+            }
+        }
+        err!(TutorialError::NoConvergence)
+    }
+
+    /// Reserve pairing with `x` on the curve of invariant `d`, in common decimals.
+    ///
+    /// Every step of Newton's method rounds up, so the result never falls
+    /// short of the exact reserve and the pool never pays out more than the
+    /// curve allows.
+    fn y(&self, x: u128, d: u128) -> Result<u128> {
+        if x == 0 {
+            return err!(TutorialError::InsufficientLiquidity);
+        }
+        // y² + (x + D/ann - D)·y = D³ / (4x·ann), iterated as
+        // y ← (y² + D³ / (4x·ann)) / (2y + x + D/ann - D), with the
+        // constant term divided in steps to stay within 256 bits
+        let b = checked(x.checked_add(d / self.ann))?;
+        let x_ann = checked(x.checked_mul(self.ann * 4))?;
+        let step = |y: u128| -> Result<u128> {
+            let denominator = checked(
+                (y * 2)
+                    .checked_add(b)
+                    .and_then(|value| value.checked_sub(d)),
+            )?;
+            let constant = mul_div_ceil(mul_div_ceil(d, d, denominator)?, d, x_ann)?;
+            checked(mul_div_ceil(y, y, denominator)?.checked_add(constant))
+        };
+        // The curve is convex, so every step after the first lands above the
+        // root and moves down towards it until rounding stalls it
+        let mut y = step(d)?;
+        for _ in 0..MAX_ITERATIONS {
+            let next = step(y)?;
+            if next >= y {
+                return Ok(y);
+            }
+            y = next;
+        }
+        err!(TutorialError::NoConvergence)
+    }
+}
+
+impl Curve for StableCurve {
+    /// Invariant `D` of the reserves, in common decimals. No trade may decrease it.
+    fn invariant(&self, reserve_a: u64, reserve_b: u64) -> Result<u128> {
+        let (x, y) = self.scale(reserve_a, reserve_b);
+        self.d(x, y)
+    }
+
+    fn swap_out(
+        &self,
+        swap_a: bool,
+        input: u64,
+        fee: u16,
+        reserve_a: u64,
+        reserve_b: u64,
+    ) -> Result<(u64, u64)> {
+        let taxed_input = curve::apply_fee(input, fee)?;
+        let (x, y) = self.scale(reserve_a, reserve_b);
+        let (reserve_in, reserve_out) = if swap_a { (x, y) } else { (y, x) };
+        let (rate_in, rate_out) = self.rates(swap_a);
+
+        let d = self.d(x, y)?;
+        let reserve_out_after = self.y(
+            checked(reserve_in.checked_add(taxed_input as u128 * rate_in))?,
+            d,
+        )?;
+        // One unit of margin for the rounding of D
+        let output = reserve_out
+            .saturating_sub(reserve_out_after)
+            .saturating_sub(1);
+        Ok((taxed_input, div_floor(output, rate_out)?))
+    }
+
+    fn swap_in(
+        &self,
+        swap_a: bool,
+        output: u64,
+        fee: u16,
+        reserve_a: u64,
+        reserve_b: u64,
+    ) -> Result<(u64, u64)> {
+        let (x, y) = self.scale(reserve_a, reserve_b);
+        let (reserve_in, reserve_out) = if swap_a { (x, y) } else { (y, x) };
+        let (rate_in, rate_out) = self.rates(swap_a);
+        if output as u128 * rate_out >= reserve_out {
+            return err!(TutorialError::InsufficientLiquidity);
+        }
+
+        let d = self.d(x, y)?;
+        let reserve_in_after = self.y(reserve_out - output as u128 * rate_out, d)?;
+        // One unit of margin for the rounding of D
+        let taxed_input = div_ceil((reserve_in_after + 1).saturating_sub(reserve_in), rate_in)?;
+        Ok((curve::add_fee(taxed_input, fee)?, taxed_input))
+    }
+
+    /// Valued by how much the deposit grows `D`. The imbalance is charged half
+    /// the trading fee, measured against a deposit growing both reserves by the
+    /// ratio `D` grows by.
+    fn deposit(
+        &self,
+        amount_a: u64,
+        amount_b: u64,
+        fee: u16,
+        reserve_a: u64,
+        reserve_b: u64,
+        supply: u64,
+    ) -> Result<u64> {
+        let new_a = reserve_a
+            .checked_add(amount_a)
+            .ok_or(TutorialError::MathOverflow)?;
+        let new_b = reserve_b
+            .checked_add(amount_b)
+            .ok_or(TutorialError::MathOverflow)?;
+        let d_after = self.invariant(new_a, new_b)?;
+
+        if supply == 0 {
+            // Lock some minimum liquidity on the first deposit
+            let liquidity = to_amount(d_after)?;
+            if liquidity < MINIMUM_LIQUIDITY {
+                return err!(TutorialError::DepositTooSmall);
+            }
+            return Ok(liquidity - MINIMUM_LIQUIDITY);
+        }
+
+        let d_before = self.invariant(reserve_a, reserve_b)?;
+        if d_before == 0 {
+            return err!(TutorialError::InsufficientLiquidity);
+        }
+        let imbalance_fee = |reserve: u64, new_reserve: u64| -> Result<u64> {
+            let balanced = to_amount(mul_div(d_after, reserve as u128, d_before)?)?;
+            let difference = balanced.abs_diff(new_reserve);
+            div_ceil(
+                difference as u128 * fee as u128,
+                2 * FEE_DENOMINATOR as u128,
+            )
+        };
+        let d_charged = self.invariant(
+            new_a - imbalance_fee(reserve_a, new_a)?,
+            new_b - imbalance_fee(reserve_b, new_b)?,
+        )?;
+
+        let liquidity = to_amount(mul_div(
+            supply as u128,
+            d_charged.saturating_sub(d_before),
+            d_before,
+        )?)?;
+        if liquidity == 0 {
+            return err!(TutorialError::DepositTooSmall);
+        }
+        Ok(liquidity)
+    }
+
+    fn spot_price(&self, base_a: bool, reserve_a: u64, reserve_b: u64) -> Option<u128> {
+        let (x, y) = self.scale(reserve_a, reserve_b);
+        let d = self.d(x, y).ok()?;
+        if d == 0 {
+            return None;
+        }
+        // Slope of the invariant, y(x + k) / x(y + k) for the base x with k = D³ / 4xy·ann
+        let k = mul_div(mul_div(d, d, x * 2).ok()?, d, y.checked_mul(self.ann * 2)?).ok()?;
+        let (base, quote) = if base_a { (x, y) } else { (y, x) };
+        let price = mul_div(mul_div(quote, base + k, base).ok()?, 1 << 64, quote + k).ok()?;
+        // From common decimals back to units of each token
+        let (rate_base, rate_quote) = self.rates(base_a);
+        mul_div(price, rate_base, rate_quote).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::tests::Rng;
+
+    const FEE: u16 = 4;
+
+    #[test]
+    fn balanced_invariant_is_the_sum_of_the_reserves() {
+        let curve = StableCurve::new(100, 6, 6).unwrap();
+        assert_eq!(curve.invariant(1_000_000, 1_000_000).unwrap(), 2_000_000);
+        assert_eq!(curve.invariant(0, 1_000_000).unwrap(), 0);
+        // Imbalanced reserves are worth less than their sum, but more than constant product's 2√xy
+        let d = curve.invariant(1_000_000, 3_000_000).unwrap();
+        assert!(d < 4_000_000 && d > 3_464_101);
+    }
+
+    #[test]
+    fn decimals_are_normalized() {
+        // 1 token of 6 decimals against 1 token of 9 decimals
+        let curve = StableCurve::new(100, 6, 9).unwrap();
+        assert_eq!(
+            curve.invariant(1_000_000, 1_000_000_000).unwrap(),
+            2_000_000_000
+        );
+        let (_, output) = curve
+            .swap_out(true, 1_000, 0, 1_000_000_000, 1_000_000_000_000)
+            .unwrap();
+        assert!(output > 999_000 && output < 1_000_000);
+
+        assert_eq!(
+            StableCurve::new(0, 6, 6).unwrap_err(),
+            TutorialError::InvalidAmplification.into()
+        );
+        assert_eq!(
+            StableCurve::new(MAX_AMPLIFICATION + 1, 6, 6).unwrap_err(),
+            TutorialError::InvalidAmplification.into()
+        );
+    }
+
+    #[test]
+    fn rejects_decimals_gaps_above_nine() {
+        assert!(StableCurve::new(100, 0, 9).is_ok());
+        assert_eq!(
+            StableCurve::new(100, 0, 10).unwrap_err(),
+            TutorialError::DecimalsGapTooLarge.into()
+        );
+        assert_eq!(
+            StableCurve::new(100, 12, 0).unwrap_err(),
+            TutorialError::DecimalsGapTooLarge.into()
+        );
+    }
+
+    #[test]
+    fn balanced_swaps_pay_almost_one_for_one() {
+        let curve = StableCurve::new(100, 6, 6).unwrap();
+        let (taxed, output) = curve
+            .swap_out(true, 1_000_000, 0, 100_000_000_000, 100_000_000_000)
+            .unwrap();
+        assert_eq!(taxed, 1_000_000);
+        assert!(output > 999_990 && output < 1_000_000);
+        // Constant product would only pay 990_099 for 1% of the reserves
+        let (_, output) = curve
+            .swap_out(true, 1_000_000_000, 0, 100_000_000_000, 100_000_000_000)
+            .unwrap();
+        assert!(output > 999_000_000);
+    }
+
+    #[test]
+    fn imbalanced_pools_pay_less_for_the_scarce_token() {
+        let curve = StableCurve::new(10, 6, 6).unwrap();
+        let (_, scarce) = curve
+            .swap_out(true, 1_000_000, 0, 190_000_000, 10_000_000)
+            .unwrap();
+        let (_, plentiful) = curve
+            .swap_out(false, 1_000_000, 0, 190_000_000, 10_000_000)
+            .unwrap();
+        assert!(scarce < 1_000_000 && plentiful > 1_000_000);
+    }
+
+    #[test]
+    fn spot_price_follows_the_marginal_rate() {
+        let curve = StableCurve::new(100, 6, 6).unwrap();
+        assert_eq!(curve.spot_price(true, 1_000_000, 1_000_000), Some(1 << 64));
+        assert_eq!(curve.spot_price(true, 0, 1_000_000), None);
+        // One token of 6 decimals is worth 1000 units of a token of 9 decimals at the peg
+        let curve = StableCurve::new(100, 6, 9).unwrap();
+        assert_eq!(
+            curve.spot_price(true, 1_000_000, 1_000_000_000),
+            Some(1000 << 64)
+        );
+        assert_eq!(
+            curve.spot_price(false, 1_000_000, 1_000_000_000),
+            Some((1 << 64) / 1000)
+        );
+
+        // Small trades on imbalanced reserves get the spot price
+        let curve = StableCurve::new(10, 6, 6).unwrap();
+        for swap_a in [true, false] {
+            let price = curve
+                .spot_price(swap_a, 190_000_000_000, 10_000_000_000)
+                .unwrap();
+            let (_, output) = curve
+                .swap_out(swap_a, 1_000_000, 0, 190_000_000_000, 10_000_000_000)
+                .unwrap();
+            let rate = ((output as u128) << 64) / 1_000_000;
+            assert!(
+                rate <= price && price - rate < price / 10_000,
+                "{rate} {price}"
+            );
+        }
+    }
+
+    #[test]
+    fn supports_full_u64_reserves() {
+        let curve = StableCurve::new(MAX_AMPLIFICATION, 9, 9).unwrap();
+        let d = curve.invariant(u64::MAX, u64::MAX).unwrap();
+        assert_eq!(d, 2 * u64::MAX as u128);
+        let (_, output) = curve
+            .swap_out(true, 1_000_000, FEE, u64::MAX / 2, u64::MAX)
+            .unwrap();
+        assert!(output > 999_000 && output < 1_000_000);
+    }
+
+    #[test]
+    fn first_deposit_mints_d_less_the_locked_liquidity() {
+        let curve = StableCurve::new(100, 6, 6).unwrap();
+        assert_eq!(
+            curve.deposit(1_000_000, 1_000_000, FEE, 0, 0, 0).unwrap(),
+            2_000_000 - MINIMUM_LIQUIDITY
+        );
+        assert_eq!(
+            curve.deposit(1_000_000, 0, FEE, 0, 0, 0).unwrap_err(),
+            TutorialError::DepositTooSmall.into()
+        );
+    }
+
+    #[test]
+    fn imbalanced_deposits_pay_a_fee() {
+        let curve = StableCurve::new(100, 6, 6).unwrap();
+        let (reserve, supply) = (100_000_000, 200_000_000);
+        let balanced = curve
+            .deposit(1_000_000, 1_000_000, FEE, reserve, reserve, supply)
+            .unwrap();
+        let one_sided = curve
+            .deposit(2_000_000, 0, FEE, reserve, reserve, supply)
+            .unwrap();
+        assert!(balanced >= 2_000_000 - 1);
+        assert!(one_sided < balanced);
+    }
+
+    const ROUNDS: usize = 2_000;
+
+    /// Pool between mints of 6 to 9 decimals whose reserves are worth at
+    /// most 100 times one another.
+    fn random_pool(rng: &mut Rng) -> (StableCurve, u64, u64) {
+        let (decimals_a, decimals_b) = (rng.between(6, 9) as u32, rng.between(6, 9) as u32);
+        let amplification = rng.between(1, 5_000);
+        let tokens = rng.between(1, 100_000_000);
+        let reserve_a = tokens * 10u64.pow(decimals_a) + rng.between(0, 10u64.pow(decimals_a));
+        let reserve_b = tokens * rng.between(1, 10_000) / 100 * 10u64.pow(decimals_b)
+            + rng.between(1, 10u64.pow(decimals_b));
+        if rng.between(0, 1) == 1 {
+            let curve =
+                StableCurve::new(amplification, decimals_a as u8, decimals_b as u8).unwrap();
+            (curve, reserve_a, reserve_b)
+        } else {
+            let curve =
+                StableCurve::new(amplification, decimals_b as u8, decimals_a as u8).unwrap();
+            (curve, reserve_b, reserve_a)
+        }
+    }
+
+    /// Reserves after a swap paying `input` into the pool and `output` out of it.
+    fn after_swap(
+        swap_a: bool,
+        input: u64,
+        output: u64,
+        reserve_a: u64,
+        reserve_b: u64,
+    ) -> Option<(u64, u64)> {
+        if swap_a {
+            Some((reserve_a.checked_add(input)?, reserve_b - output))
+        } else {
+            Some((reserve_a - output, reserve_b.checked_add(input)?))
+        }
+    }
+
+    #[test]
+    fn swaps_never_decrease_the_invariant() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut priced = 0;
+        for _ in 0..ROUNDS {
+            let (curve, reserve_a, reserve_b) = random_pool(&mut rng);
+            let fee = rng.between(0, 100) as u16;
+            let swap_a = rng.between(0, 1) == 1;
+            let input = rng.between(1, if swap_a { reserve_a } else { reserve_b });
+
+            let (_, output) = curve
+                .swap_out(swap_a, input, fee, reserve_a, reserve_b)
+                .unwrap();
+            let Some((reserve_a_after, reserve_b_after)) =
+                after_swap(swap_a, input, output, reserve_a, reserve_b)
+            else {
+                continue;
+            };
+            assert!(
+                curve.invariant(reserve_a_after, reserve_b_after).unwrap()
+                    >= curve.invariant(reserve_a, reserve_b).unwrap()
+            );
+            priced += 1;
+        }
+        assert!(priced > ROUNDS * 9 / 10);
+    }
+
+    #[test]
+    fn exact_output_swaps_never_underpay() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut priced = 0;
+        for _ in 0..ROUNDS {
+            let (curve, reserve_a, reserve_b) = random_pool(&mut rng);
+            let fee = rng.between(0, 100) as u16;
+            let swap_a = rng.between(0, 1) == 1;
+            let output = rng.between(1, if swap_a { reserve_b } else { reserve_a } - 1);
+
+            let (input, _) = curve
+                .swap_in(swap_a, output, fee, reserve_a, reserve_b)
+                .unwrap();
+            let Some((reserve_a_after, reserve_b_after)) =
+                after_swap(swap_a, input, output, reserve_a, reserve_b)
+            else {
+                continue;
+            };
+            assert!(
+                curve.invariant(reserve_a_after, reserve_b_after).unwrap()
+                    >= curve.invariant(reserve_a, reserve_b).unwrap()
+            );
+            priced += 1;
+        }
+        assert!(priced > ROUNDS * 9 / 10);
+    }
+
+    #[test]
+    fn deposits_never_dilute_liquidity() {
+        let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+        for _ in 0..ROUNDS {
+            let (curve, reserve_a, reserve_b) = random_pool(&mut rng);
+            let supply = rng.between(1, u32::MAX as u64);
+            let amount_a = rng.between(0, reserve_a);
+            let amount_b = rng.between(0, reserve_b);
+
+            let Ok(liquidity) =
+                curve.deposit(amount_a, amount_b, FEE, reserve_a, reserve_b, supply)
+            else {
+                continue;
+            };
+            // D per liquidity token never decreases
+            let d_before = curve.invariant(reserve_a, reserve_b).unwrap();
+            let d_after = curve
+                .invariant(reserve_a + amount_a, reserve_b + amount_b)
+                .unwrap();
+            assert!(d_after * supply as u128 >= d_before * (supply + liquidity) as u128);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Token-2022 transfer fees withheld on transfers into and out of pools.
+//!
+//! Mints owned by the SPL Token program, or Token-2022 mints without the
+//! `TransferFeeConfig` extension, never withhold anything.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFee, TransferFeeConfig},
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    state::Mint,
+};
+
+use crate::TutorialError;
+
+/// Transfer fee of `mint` for the current epoch, if it charges one.
+fn epoch_fee(mint: &AccountInfo) -> Result<Option<TransferFee>> {
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<Mint>::unpack(&data)?;
+    match state.get_extension::<TransferFeeConfig>() {
+        Ok(config) => Ok(Some(*config.get_epoch_fee(Clock::get()?.epoch))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Amount arriving at the destination when `amount` of `mint` is sent.
+pub fn amount_received(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    match epoch_fee(mint)? {
+        Some(fee) => fee
+            .calculate_post_fee_amount(amount)
+            .ok_or_else(|| error!(TutorialError::MathOverflow)),
+        None => Ok(amount),
+    }
+}
+
+/// Amount of `mint` to send so that at least `received` arrives at the destination.
+pub fn amount_to_send(mint: &AccountInfo, received: u64) -> Result<u64> {
+    match epoch_fee(mint)? {
+        Some(fee) => fee
+            .calculate_pre_fee_amount(received)
+            .ok_or_else(|| error!(TutorialError::MathOverflow)),
+        None => Ok(received),
+    }
+}
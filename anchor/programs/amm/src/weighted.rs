@@ -0,0 +1,504 @@
+//! Weighted-product pricing for pools whose tokens do not carry equal weight,
+//! after Balancer's invariant:
+//!
+//! ```text
+//! Ba^wa · Bb^wb = V
+//! ```
+//!
+//! The weights are the shares of the pool's value held in each token, so an
+//! 80/20 pool keeps most of its liquidity in token A while still quoting it
+//! against B. Swaps pay `out = Bo · (1 - (Bi / (Bi + Ai))^(wi / wo))`, which is
+//! constant product when both weights are equal.
+//!
+//! Powers with fractional exponents are taken through base-2 logarithms and
+//! exponentials in integer fixed point, so every validator computes the same
+//! result. Their error is far below one part in 2^`MARGIN_BITS`, the margin by
+//! which every reserve left after a trade is rounded up, so the approximations
+//! always fall in favour of the pool.
+//!
+//! Withdrawals pay out the same share of both reserves, which keeps the split
+//! of the pool's value along its weights, so they need nothing from this module.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    curve::{self, checked, mul_div, mul_div_ceil, to_amount, wide_mul, Curve},
+    TutorialError, MINIMUM_LIQUIDITY,
+};
+
+/// Denominator of the weights, which are expressed in basis points.
+pub const WEIGHT_DENOMINATOR: u16 = 10000;
+
+/// Smallest accepted weight, 1% of the pool.
+pub const MIN_WEIGHT: u16 = 100;
+
+/// Fractional bits of the Q64.64 logarithms.
+const LOG_FRACTIONAL_BITS: u32 = 64;
+
+/// Fractional bits of the mantissas the logarithms and exponentials work on.
+const MANTISSA_BITS: u32 = 126;
+
+/// ln 2 with `MANTISSA_BITS` fractional bits.
+const LN_2: u128 = 0x2c5c_85fd_f473_de6a_f278_ece6_00fc_bdab;
+
+/// Reserves left after a trade are rounded up by one part in 2^MARGIN_BITS.
+const MARGIN_BITS: u32 = 48;
+
+/// Product of two numbers with `MANTISSA_BITS` fractional bits, rounded down.
+fn mul_mantissa(a: u128, b: u128) -> u128 {
+    let (high, low) = wide_mul(a, b);
+    (high << (128 - MANTISSA_BITS)) | (low >> MANTISSA_BITS)
+}
+
+/// Base-2 logarithm of `value` as a Q64.64 number, rounded down.
+fn log2(value: u64) -> Result<u128> {
+    if value == 0 {
+        return err!(TutorialError::InsufficientLiquidity);
+    }
+    let integer = 63 - value.leading_zeros();
+    let mut log = (integer as u128) << LOG_FRACTIONAL_BITS;
+
+    // `value / 2^integer`, in [1, 2). Squaring it doubles its logarithm, so
+    // every square reaching 2 sets the next fractional bit
+    let mut mantissa = (value as u128) << (MANTISSA_BITS - integer);
+    for bit in (0..LOG_FRACTIONAL_BITS).rev() {
+        mantissa = mul_mantissa(mantissa, mantissa);
+        if mantissa >> (MANTISSA_BITS + 1) != 0 {
+            mantissa >>= 1;
+            log |= 1 << bit;
+        }
+    }
+    Ok(log)
+}
+
+/// 2 to the power of a Q64.64 `fraction` below one, with `MANTISSA_BITS`
+/// fractional bits, rounded down.
+fn exp2_fraction(fraction: u128) -> u128 {
+    // Taylor series of e^(fraction · ln 2), whose terms are all positive
+    let exponent = mul_mantissa(fraction << (MANTISSA_BITS - LOG_FRACTIONAL_BITS), LN_2);
+    let mut sum = 1 << MANTISSA_BITS;
+    let mut term = 1 << MANTISSA_BITS;
+    let mut k = 1;
+    while term > 0 {
+        term = mul_mantissa(term, exponent) / k;
+        sum += term;
+        k += 1;
+    }
+    sum
+}
+
+/// `amount · 2^exponent` for a Q64.64 `exponent`. Rounded up plus the margin
+/// if `round_up`, otherwise rounded down less the margin.
+fn scale(amount: u64, exponent: i128, round_up: bool) -> Result<u128> {
+    let integer = exponent >> LOG_FRACTIONAL_BITS;
+    let fraction = (exponent - (integer << LOG_FRACTIONAL_BITS)) as u128;
+    let (high, low) = wide_mul(amount as u128, exp2_fraction(fraction));
+
+    // Drop the mantissa's fractional bits, less those the integer part shifts in
+    let shift = MANTISSA_BITS as i128 - integer;
+    let value = if shift >= 256 {
+        0
+    } else if shift >= 128 {
+        high >> (shift - 128)
+    } else if shift > 0 && high >> shift == 0 {
+        (high << (128 - shift)) | (low >> shift)
+    } else if shift == 0 && high == 0 {
+        low
+    } else {
+        return err!(TutorialError::MathOverflow);
+    };
+
+    let margin = value >> MARGIN_BITS;
+    if round_up {
+        checked(value.checked_add(margin + 2))
+    } else {
+        Ok(value.saturating_sub(margin + 1))
+    }
+}
+
+/// Weighted-product curve of one pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeightedCurve {
+    /// Share of the pool's value in token A, in basis points
+    weight_a: u16,
+    /// Share of the pool's value in token B, in basis points
+    weight_b: u16,
+}
+
+impl WeightedCurve {
+    /// Curve keeping `weight_a` and `weight_b` basis points of the pool's value in token A and B.
+    pub fn new(weight_a: u16, weight_b: u16) -> Result<Self> {
+        if weight_a < MIN_WEIGHT || weight_b < MIN_WEIGHT || weight_a.checked_add(weight_b) != Some(WEIGHT_DENOMINATOR) {
+            return err!(TutorialError::InvalidWeights);
+        }
+        Ok(Self { weight_a, weight_b })
+    }
+
+    /// Weights of the token going into and out of the pool.
+    fn weights(&self, swap_a: bool) -> (u128, u128) {
+        if swap_a {
+            (self.weight_a as u128, self.weight_b as u128)
+        } else {
+            (self.weight_b as u128, self.weight_a as u128)
+        }
+    }
+
+    /// `V` of the reserves, their weighted geometric mean, rounded down.
+    fn value(&self, reserve_a: u64, reserve_b: u64) -> Result<u64> {
+        let log = self.invariant(reserve_a, reserve_b)? / WEIGHT_DENOMINATOR as u128;
+        to_amount(scale(1, log as i128, false)?)
+    }
+}
+
+impl Curve for WeightedCurve {
+    /// `wa · log2 Ba + wb · log2 Bb` as a Q64.64 number, the logarithm of `V`
+    /// times `WEIGHT_DENOMINATOR`, rounded down. No trade may decrease it.
+    ///
+    /// Zero if either reserve is empty.
+    fn invariant(&self, reserve_a: u64, reserve_b: u64) -> Result<u128> {
+        if reserve_a == 0 || reserve_b == 0 {
+            return Ok(0);
+        }
+        Ok(log2(reserve_a)? * self.weight_a as u128 + log2(reserve_b)? * self.weight_b as u128)
+    }
+
+    fn swap_out(&self, swap_a: bool, input: u64, fee: u16, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)> {
+        let taxed_input = curve::apply_fee(input, fee)?;
+        let (reserve_in, reserve_out) = if swap_a { (reserve_a, reserve_b) } else { (reserve_b, reserve_a) };
+        let (weight_in, weight_out) = self.weights(swap_a);
+        if reserve_in == 0 || reserve_out == 0 {
+            return err!(TutorialError::InsufficientLiquidity);
+        }
+        let new_reserve_in = reserve_in.checked_add(taxed_input).ok_or(TutorialError::MathOverflow)?;
+
+        // Bo · (Bi / (Bi + Ai))^(wi / wo), the part of the reserve the trade leaves
+        let exponent = mul_div(log2(new_reserve_in)? - log2(reserve_in)?, weight_in, weight_out)?;
+        let reserve_out_after = scale(reserve_out, -(exponent as i128), true)?;
+        let output = (reserve_out as u128).saturating_sub(reserve_out_after);
+        Ok((taxed_input, output as u64))
+    }
+
+    fn swap_in(&self, swap_a: bool, output: u64, fee: u16, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)> {
+        let (reserve_in, reserve_out) = if swap_a { (reserve_a, reserve_b) } else { (reserve_b, reserve_a) };
+        let (weight_in, weight_out) = self.weights(swap_a);
+        if reserve_in == 0 || output >= reserve_out {
+            return err!(TutorialError::InsufficientLiquidity);
+        }
+
+        // Bi · (Bo / (Bo - Ao))^(wo / wi), the reserve the trade requires
+        let exponent = mul_div_ceil(log2(reserve_out)? - log2(reserve_out - output)?, weight_out, weight_in)?;
+        let reserve_in_after = scale(reserve_in, exponent as i128, true)?;
+        let taxed_input = to_amount(reserve_in_after - reserve_in as u128)?;
+        Ok((curve::add_fee(taxed_input, fee)?, taxed_input))
+    }
+
+    /// Valued by how much the deposit grows `V`. The imbalance is the part of
+    /// each reserve growing faster than the weighted average of both.
+    fn deposit(
+        &self,
+        amount_a: u64,
+        amount_b: u64,
+        fee: u16,
+        reserve_a: u64,
+        reserve_b: u64,
+        supply: u64,
+    ) -> Result<u64> {
+        if supply == 0 {
+            // Lock some minimum liquidity on the first deposit
+            let liquidity = self.value(amount_a, amount_b)?;
+            if liquidity < MINIMUM_LIQUIDITY {
+                return err!(TutorialError::DepositTooSmall);
+            }
+            return Ok(liquidity - MINIMUM_LIQUIDITY);
+        }
+        if reserve_a == 0 || reserve_b == 0 {
+            return err!(TutorialError::InsufficientLiquidity);
+        }
+
+        // Weighted average growth of the reserves, as a Q64.64 number
+        let growth = |amount: u64, reserve: u64, weight: u16| {
+            mul_div((amount as u128) << 64, weight as u128, reserve as u128 * WEIGHT_DENOMINATOR as u128)
+        };
+        let average_growth = checked(
+            growth(amount_a, reserve_a, self.weight_a)?.checked_add(growth(amount_b, reserve_b, self.weight_b)?),
+        )?;
+        let after_fee = |amount: u64, reserve: u64| -> Result<u64> {
+            let proportional = mul_div(reserve as u128, average_growth, 1 << 64)?;
+            let excess = to_amount((amount as u128).saturating_sub(proportional))?;
+            Ok(amount - curve::fee_amount(excess, fee)?)
+        };
+        let new_reserve_a = reserve_a
+            .checked_add(after_fee(amount_a, reserve_a)?)
+            .ok_or(TutorialError::MathOverflow)?;
+        let new_reserve_b = reserve_b
+            .checked_add(after_fee(amount_b, reserve_b)?)
+            .ok_or(TutorialError::MathOverflow)?;
+
+        // The supply grows with V
+        let log_growth = self
+            .invariant(new_reserve_a, new_reserve_b)?
+            .saturating_sub(self.invariant(reserve_a, reserve_b)?)
+            / WEIGHT_DENOMINATOR as u128;
+        let liquidity = to_amount(scale(supply, log_growth as i128, false)?.saturating_sub(supply as u128))?;
+        if liquidity == 0 {
+            return err!(TutorialError::DepositTooSmall);
+        }
+        Ok(liquidity)
+    }
+
+    /// `(Bq / wq) / (Bb / wb)` for the base token b and the quote token q.
+    fn spot_price(&self, base_a: bool, reserve_a: u64, reserve_b: u64) -> Option<u128> {
+        let (reserve_base, reserve_quote) = if base_a { (reserve_a, reserve_b) } else { (reserve_b, reserve_a) };
+        let (weight_base, weight_quote) = self.weights(base_a);
+        mul_div((reserve_quote as u128) << 64, weight_base, reserve_base as u128 * weight_quote).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dashu_float::{round::mode::HalfEven, FBig};
+
+    use super::*;
+    use crate::curve::tests::Rng;
+
+    const FEE: u16 = 30;
+
+    /// High-precision reference arithmetic.
+    type Real = FBig<HalfEven>;
+
+    fn real(value: impl Into<Real>) -> Real {
+        value.into().with_precision(256).value()
+    }
+
+    fn ratio(numerator: u64, denominator: u64) -> Real {
+        real(numerator) / real(denominator)
+    }
+
+    /// Q64.64 number as a real.
+    fn from_q64(value: u128) -> Real {
+        real(value) / real(1u128 << 64)
+    }
+
+    /// `|a - b|` within `tolerance`.
+    fn close(a: &Real, b: &Real, tolerance: &Real) -> bool {
+        let difference = a - b;
+        difference <= *tolerance && -difference <= *tolerance
+    }
+
+    /// Bo · (1 - (Bi / (Bi + Ai))^(wi / wo)).
+    fn reference_output(weight_in: u16, weight_out: u16, reserve_in: u64, reserve_out: u64, taxed_input: u64) -> Real {
+        let base = ratio(reserve_in, reserve_in + taxed_input);
+        real(reserve_out) * (real(1) - base.powf(&ratio(weight_in as u64, weight_out as u64)))
+    }
+
+    /// Bi · ((Bo / (Bo - Ao))^(wo / wi) - 1).
+    fn reference_input(weight_in: u16, weight_out: u16, reserve_in: u64, reserve_out: u64, output: u64) -> Real {
+        let base = ratio(reserve_out, reserve_out - output);
+        real(reserve_in) * (base.powf(&ratio(weight_out as u64, weight_in as u64)) - real(1))
+    }
+
+    /// Ba^wa · Bb^wb.
+    fn reference_value(weight_a: u16, weight_b: u16, reserve_a: u64, reserve_b: u64) -> Real {
+        let power = |reserve: u64, weight: u16| real(reserve).powf(&ratio(weight as u64, WEIGHT_DENOMINATOR as u64));
+        power(reserve_a, weight_a) * power(reserve_b, weight_b)
+    }
+
+    #[test]
+    fn log2_matches_the_reference() {
+        assert_eq!(log2(1).unwrap(), 0);
+        assert_eq!(log2(1 << 40).unwrap(), 40 << 64);
+        assert_eq!(log2(0).unwrap_err(), TutorialError::InsufficientLiquidity.into());
+        let tolerance = real(1) / real(1u128 << 62);
+        for value in [3, 10, 1_000_000, 123_456_789_012, u64::MAX - 1, u64::MAX] {
+            let log = from_q64(log2(value).unwrap());
+            let reference = real(value).ln() / real(2).ln();
+            assert!(log <= reference && close(&log, &reference, &tolerance), "log2({value})");
+        }
+    }
+
+    #[test]
+    fn exp2_matches_the_reference() {
+        let tolerance = real(1) / real(1u128 << 120);
+        for fraction in [0, 1, 1 << 63, (1 << 64) / 3, u64::MAX as u128] {
+            let power = real(exp2_fraction(fraction)) / real(1u128 << MANTISSA_BITS);
+            let reference = (from_q64(fraction) * real(2).ln()).exp();
+            assert!(power <= reference && close(&power, &reference, &tolerance), "2^{fraction}");
+        }
+    }
+
+    #[test]
+    fn weights_must_add_up_to_the_whole_pool() {
+        assert!(WeightedCurve::new(8000, 2000).is_ok());
+        assert!(WeightedCurve::new(MIN_WEIGHT, WEIGHT_DENOMINATOR - MIN_WEIGHT).is_ok());
+        for (weight_a, weight_b) in [(0, 10000), (99, 9901), (5000, 4000), (5000, 6000), (u16::MAX, 10001)] {
+            assert_eq!(
+                WeightedCurve::new(weight_a, weight_b).unwrap_err(),
+                TutorialError::InvalidWeights.into()
+            );
+        }
+    }
+
+    #[test]
+    fn spot_price_weighs_the_reserves() {
+        // 80% of the value in 80_000_000 A, 20% in 20_000_000 B: one A is worth one B
+        let weighted = WeightedCurve::new(8000, 2000).unwrap();
+        assert_eq!(weighted.spot_price(true, 80_000_000, 20_000_000), Some(1 << 64));
+        assert_eq!(weighted.spot_price(false, 80_000_000, 20_000_000), Some(1 << 64));
+        assert_eq!(weighted.spot_price(true, 80_000_000, 80_000_000), Some(4 << 64));
+        assert_eq!(weighted.spot_price(true, 0, 20_000_000), None);
+
+        let weighted = WeightedCurve::new(5000, 5000).unwrap();
+        assert_eq!(weighted.spot_price(false, 3_000, 7_000), crate::oracle::spot_price(7_000, 3_000));
+    }
+
+    #[test]
+    fn equal_weights_price_like_constant_product() {
+        let weighted = WeightedCurve::new(5000, 5000).unwrap();
+        for (input, reserve_a, reserve_b) in [(1_000_000, 100_000_000, 200_000_000), (7, 1_000, 1_000), (u32::MAX as u64, u64::MAX / 3, u64::MAX)] {
+            let (taxed, output) = weighted.swap_out(true, input, FEE, reserve_a, reserve_b).unwrap();
+            let (taxed_constant, output_constant) = curve::swap_output(input, FEE, reserve_a, reserve_b).unwrap();
+            assert_eq!(taxed, taxed_constant);
+            // Short by at most the margin on the reserve left and rounding
+            assert!(output <= output_constant && output + (reserve_b >> MARGIN_BITS) + 2 >= output_constant);
+        }
+    }
+
+    #[test]
+    fn heavier_tokens_move_less() {
+        // 80/20 pool where both tokens are worth the same: 80 A next to 20 B
+        let weighted = WeightedCurve::new(8000, 2000).unwrap();
+        let (_, output) = weighted.swap_out(true, 1_000_000, 0, 80_000_000, 20_000_000).unwrap();
+        let reference = reference_output(8000, 2000, 80_000_000, 20_000_000, 1_000_000);
+        assert!(real(output) <= reference && close(&real(output), &reference, &real(2)));
+        // Constant product over the same reserves would only pay 246_913
+        assert!(output > 960_000);
+    }
+
+    const ROUNDS: usize = 300;
+
+    /// Pool with random weights and reserves of up to 2^63.
+    fn random_pool(rng: &mut Rng) -> (WeightedCurve, u16, u16, u64, u64) {
+        let weight_a = rng.between(MIN_WEIGHT as u64, (WEIGHT_DENOMINATOR - MIN_WEIGHT) as u64) as u16;
+        let weight_b = WEIGHT_DENOMINATOR - weight_a;
+        let mut reserve = || {
+            let bits = rng.between(10, 63);
+            rng.between(1, 1 << bits)
+        };
+        let (reserve_a, reserve_b) = (reserve(), reserve());
+        (WeightedCurve::new(weight_a, weight_b).unwrap(), weight_a, weight_b, reserve_a, reserve_b)
+    }
+
+    /// Error allowed against the reference: the margin and a few units of rounding.
+    fn tolerance(reserve: u64) -> Real {
+        real(reserve) / real(1u64 << (MARGIN_BITS - 2)) + real(4)
+    }
+
+    #[test]
+    fn swap_output_never_exceeds_the_reference() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..ROUNDS {
+            let (weighted, weight_a, weight_b, reserve_a, reserve_b) = random_pool(&mut rng);
+            let swap_a = rng.between(0, 1) == 1;
+            let (reserve_in, reserve_out, weight_in, weight_out) = if swap_a {
+                (reserve_a, reserve_b, weight_a, weight_b)
+            } else {
+                (reserve_b, reserve_a, weight_b, weight_a)
+            };
+            let input = rng.between(1, reserve_in.min(u64::MAX - reserve_in));
+
+            let (taxed, output) = weighted.swap_out(swap_a, input, FEE, reserve_a, reserve_b).unwrap();
+            let reference = reference_output(weight_in, weight_out, reserve_in, reserve_out, taxed);
+            assert!(real(output) <= reference);
+            assert!(close(&real(output), &reference, &tolerance(reserve_out)));
+        }
+    }
+
+    #[test]
+    fn swap_input_never_falls_short_of_the_reference() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut priced = 0;
+        for _ in 0..ROUNDS {
+            let (weighted, weight_a, weight_b, reserve_a, reserve_b) = random_pool(&mut rng);
+            let swap_a = rng.between(0, 1) == 1;
+            let (reserve_in, reserve_out, weight_in, weight_out) = if swap_a {
+                (reserve_a, reserve_b, weight_a, weight_b)
+            } else {
+                (reserve_b, reserve_a, weight_b, weight_a)
+            };
+            let output = rng.between(0, reserve_out - 1);
+
+            // Draining most of a light token can ask for more than a u64 of the heavy one
+            let Ok((_, taxed)) = weighted.swap_in(swap_a, output, FEE, reserve_a, reserve_b) else {
+                continue;
+            };
+            let reference = reference_input(weight_in, weight_out, reserve_in, reserve_out, output);
+            assert!(real(taxed) >= reference);
+            assert!(close(&real(taxed), &reference, &tolerance(reserve_in + taxed)));
+            priced += 1;
+        }
+        assert!(priced > ROUNDS / 2);
+    }
+
+    #[test]
+    fn swaps_never_decrease_the_invariant() {
+        let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+        for _ in 0..ROUNDS {
+            let (weighted, _, _, reserve_a, reserve_b) = random_pool(&mut rng);
+            let swap_a = rng.between(0, 1) == 1;
+            let reserve_in = if swap_a { reserve_a } else { reserve_b };
+            let input = rng.between(1, reserve_in.min(u64::MAX - reserve_in));
+
+            let (_, output) = weighted.swap_out(swap_a, input, 0, reserve_a, reserve_b).unwrap();
+            let (reserve_a_after, reserve_b_after) = if swap_a {
+                (reserve_a + input, reserve_b - output)
+            } else {
+                (reserve_a - output, reserve_b + input)
+            };
+            if reserve_a_after > 0 && reserve_b_after > 0 {
+                assert!(weighted.invariant(reserve_a_after, reserve_b_after).unwrap() >= weighted.invariant(reserve_a, reserve_b).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn first_deposit_mints_the_weighted_geometric_mean() {
+        let weighted = WeightedCurve::new(8000, 2000).unwrap();
+        let liquidity = weighted.deposit(80_000_000, 20_000_000, FEE, 0, 0, 0).unwrap();
+        let reference = reference_value(8000, 2000, 80_000_000, 20_000_000) - real(MINIMUM_LIQUIDITY);
+        assert!(real(liquidity) <= reference && close(&real(liquidity), &reference, &real(2)));
+        assert_eq!(
+            weighted.deposit(1_000_000, 0, FEE, 0, 0, 0).unwrap_err(),
+            TutorialError::DepositTooSmall.into()
+        );
+    }
+
+    #[test]
+    fn proportional_deposits_pay_no_fee() {
+        let weighted = WeightedCurve::new(8000, 2000).unwrap();
+        let (reserve_a, reserve_b, supply) = (80_000_000, 20_000_000, 60_000_000);
+        let balanced = weighted.deposit(800_000, 200_000, FEE, reserve_a, reserve_b, supply).unwrap();
+        // 1% of both reserves mints 1% of the supply, less a few units of rounding
+        assert!((599_990..=600_000).contains(&balanced));
+        let one_sided = weighted.deposit(1_000_000, 0, FEE, reserve_a, reserve_b, supply).unwrap();
+        assert!(one_sided < balanced);
+    }
+
+    #[test]
+    fn deposits_never_dilute_liquidity() {
+        let mut rng = Rng(0x94d0_49bb_1331_11eb);
+        for _ in 0..ROUNDS {
+            let (weighted, weight_a, weight_b, reserve_a, reserve_b) = random_pool(&mut rng);
+            let supply = rng.between(1, u32::MAX as u64);
+            let amount_a = rng.between(0, reserve_a);
+            let amount_b = rng.between(0, reserve_b);
+
+            let Ok(liquidity) = weighted.deposit(amount_a, amount_b, FEE, reserve_a, reserve_b, supply) else {
+                continue;
+            };
+            // V per liquidity token never decreases
+            let before = reference_value(weight_a, weight_b, reserve_a, reserve_b) / real(supply);
+            let after = reference_value(weight_a, weight_b, reserve_a + amount_a, reserve_b + amount_b) / real(supply + liquidity);
+            assert!(after >= before);
+        }
+    }
+}
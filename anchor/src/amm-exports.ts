@@ -27,3 +27,18 @@ export function getAmmProgramId(cluster: Cluster) {
       return AMM_PROGRAM_ID
   }
 }
+
+// Orders two mints the way `create_pool` expects them, mint A sorting before mint B.
+export function sortMints(mint1: PublicKey, mint2: PublicKey): [PublicKey, PublicKey] {
+  const order = mint1.toBuffer().compare(mint2.toBuffer())
+  if (order === 0) {
+    throw new Error('A pool needs two different mints')
+  }
+  return order < 0 ? [mint1, mint2] : [mint2, mint1]
+}
+
+// Derives the address of the single pool an AMM can have for a pair of mints, in any order.
+export function getPoolAddress(amm: PublicKey, mint1: PublicKey, mint2: PublicKey, programId = AMM_PROGRAM_ID) {
+  const [mintA, mintB] = sortMints(mint1, mint2)
+  return PublicKey.findProgramAddressSync([amm.toBuffer(), mintA.toBuffer(), mintB.toBuffer()], programId)[0]
+}
@@ -1,15 +1,33 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
-import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import {
+  PublicKey,
+  Keypair,
+  SystemProgram,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  Transaction,
+  TransactionInstruction,
+  sendAndConfirmTransaction,
+} from "@solana/web3.js";
 import { 
   TOKEN_PROGRAM_ID, 
+  TOKEN_2022_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID,
+  ExtensionType,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  getMint,
+  getMintLen,
+  getOrCreateAssociatedTokenAccount,
   createMint,
   createAssociatedTokenAccount,
   mintTo,
+  transfer,
   getAssociatedTokenAddress
 } from "@solana/spl-token";
 import { Amm } from "../target/types/amm";
+import { getPoolAddress, sortMints } from "../src/amm-exports";
+import { PoolFixture, ammHelpers } from "./helpers";
 
 describe("AMM Program Tests", () => {
   const provider = anchor.AnchorProvider.env();
@@ -29,9 +47,19 @@ describe("AMM Program Tests", () => {
   let poolPda: PublicKey;
   let poolAuthority: PublicKey;
   let mintLiquidity: PublicKey;
+  let liquidityLock: PublicKey;
   let poolAccountA: PublicKey;
   let poolAccountB: PublicKey;
 
+  const { poolAddresses, createPoolFixture, createPool, swap, swapMethod, swapRoute, deposit, withdraw } = ammHelpers(() => ({
+    provider,
+    program,
+    payer,
+    user,
+    amm: ammPda,
+    rent: (space) => provider.connection.getMinimumBalanceForRentExemption(space),
+  }));
+
   beforeAll(async () => {
     // Generate test keypairs
     admin = Keypair.generate();
@@ -53,13 +81,17 @@ describe("AMM Program Tests", () => {
     // Wait for airdrops to confirm
     await new Promise(resolve => setTimeout(resolve, 1000));
 
-    // Create test tokens
+    // Create test tokens, pools require mint A to sort before mint B
+    const [mintKeypairA, mintKeypairB] = [Keypair.generate(), Keypair.generate()].sort((x, y) =>
+      x.publicKey.toBuffer().compare(y.publicKey.toBuffer())
+    );
     mintA = await createMint(
       provider.connection,
       payer,
       payer.publicKey,
       null,
-      6 // decimals
+      6, // decimals
+      mintKeypairA
     );
 
     mintB = await createMint(
@@ -67,7 +99,8 @@ describe("AMM Program Tests", () => {
       payer,
       payer.publicKey,
       null,
-      6 // decimals
+      6, // decimals
+      mintKeypairB
     );
 
     // Calculate remaining PDAs after mints are created
@@ -86,6 +119,11 @@ describe("AMM Program Tests", () => {
       program.programId
     );
 
+    [liquidityLock] = PublicKey.findProgramAddressSync(
+      [ammPda.toBuffer(), mintA.toBuffer(), mintB.toBuffer(), Buffer.from("lock")],
+      program.programId
+    );
+
     poolAccountA = await getAssociatedTokenAddress(mintA, poolAuthority, true);
     poolAccountB = await getAssociatedTokenAddress(mintB, poolAuthority, true);
 
@@ -127,7 +165,7 @@ describe("AMM Program Tests", () => {
   it("Creates an AMM", async () => {
     const fee = 300; // 3% fee (300 basis points)
     
-    await program.methods
+    const signature = await program.methods
       .createAmm(ammId, fee)
       .accountsPartial({
         payer: payer.publicKey,
@@ -142,22 +180,32 @@ describe("AMM Program Tests", () => {
     expect(ammAccount.id.toString()).toEqual(ammId.toString());
     expect(ammAccount.admin.toString()).toEqual(admin.publicKey.toString());
     expect(ammAccount.fee).toEqual(fee);
+
+    const ammCreated = (await parseEvents(signature)).find((event) => event.name === "ammCreated");
+    expect(ammCreated).toBeDefined();
+    expect(ammCreated!.data.amm.toString()).toEqual(ammPda.toString());
+    expect(ammCreated!.data.id.toString()).toEqual(ammAccount.id.toString());
+    expect(ammCreated!.data.admin.toString()).toEqual(ammAccount.admin.toString());
+    expect(ammCreated!.data.fee).toEqual(ammAccount.fee);
   });
 
   it("Creates a pool", async () => {
-    await program.methods
-      .createPool()
+    const signature = await program.methods
+      .createPool({ constantProduct: {} }, new Array(32).fill(0))
       .accountsPartial({
         amm: ammPda,
         pool: poolPda,
         poolAuthority: poolAuthority,
         mintLiquidity: mintLiquidity,
+        liquidityLock: liquidityLock,
         mintA: mintA,
         mintB: mintB,
         poolAccountA: poolAccountA,
         poolAccountB: poolAccountB,
         payer: payer.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgramA: TOKEN_PROGRAM_ID,
+        tokenProgramB: TOKEN_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
@@ -166,8 +214,19 @@ describe("AMM Program Tests", () => {
 
     const poolAccount = await program.account.pool.fetch(poolPda);
     expect(poolAccount.amm.toString()).toEqual(ammPda.toString());
+    expect(poolAccount.curveType).toEqual({ constantProduct: {} });
     expect(poolAccount.mintA.toString()).toEqual(mintA.toString());
     expect(poolAccount.mintB.toString()).toEqual(mintB.toString());
+
+    const poolCreated = (await parseEvents(signature)).find((event) => event.name === "poolCreated");
+    expect(poolCreated).toBeDefined();
+    expect(poolCreated!.data.amm.toString()).toEqual(poolAccount.amm.toString());
+    expect(poolCreated!.data.pool.toString()).toEqual(poolPda.toString());
+    expect(poolCreated!.data.mintA.toString()).toEqual(poolAccount.mintA.toString());
+    expect(poolCreated!.data.mintB.toString()).toEqual(poolAccount.mintB.toString());
+    expect(poolCreated!.data.mintLiquidity.toString()).toEqual(mintLiquidity.toString());
+    expect(poolCreated!.data.curveType).toEqual(poolAccount.curveType);
+    expect(poolCreated!.data.curveParams).toEqual(poolAccount.curveParams);
   });
 
   it("Deposits liquidity", async () => {
@@ -177,13 +236,15 @@ describe("AMM Program Tests", () => {
     const amountB = 100000000; // 100 tokens with 6 decimals
 
     await program.methods
-      .depositLiquidity(new anchor.BN(amountA), new anchor.BN(amountB))
+      .depositLiquidity(new anchor.BN(amountA), new anchor.BN(amountB), new anchor.BN(0), null)
       .accountsPartial({
         payer: payer.publicKey,
+        amm: ammPda,
         pool: poolPda,
         poolAuthority: poolAuthority,
         depositor: user.publicKey,
         mintLiquidity: mintLiquidity,
+        liquidityLock: liquidityLock,
         mintA: mintA,
         mintB: mintB,
         poolAccountA: poolAccountA,
@@ -192,6 +253,8 @@ describe("AMM Program Tests", () => {
         depositorAccountA: userAccountA,
         depositorAccountB: userAccountB,
         tokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgramA: TOKEN_PROGRAM_ID,
+        tokenProgramB: TOKEN_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
@@ -218,11 +281,13 @@ describe("AMM Program Tests", () => {
       .swapExactTokensForTokens(
         true, // swap_a (swap token A for token B)
         new anchor.BN(swapAmount),
-        new anchor.BN(minOutput)
+        new anchor.BN(minOutput),
+        null // no deadline
       )
       .accountsPartial({
         amm: ammPda,
         pool: poolPda,
+        observations: null,
         poolAuthority: poolAuthority,
         trader: user.publicKey,
         mintA: mintA,
@@ -232,7 +297,8 @@ describe("AMM Program Tests", () => {
         traderAccountA: userAccountA,
         traderAccountB: userAccountB,
         payer: payer.publicKey,
-        tokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgramA: TOKEN_PROGRAM_ID,
+        tokenProgramB: TOKEN_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
@@ -248,6 +314,914 @@ describe("AMM Program Tests", () => {
     expect(Number(userBalanceBAfter.value.amount)).toBeGreaterThan(Number(userBalanceBBefore.value.amount));
   });
 
+  it("Performs an exact-output swap", async () => {
+    const outputAmount = 500000; // 0.5 token with 6 decimals
+    const maxInput = 1000000; // 1 token with 6 decimals
+
+    const userBalanceABefore = await provider.connection.getTokenAccountBalance(userAccountA);
+    const userBalanceBBefore = await provider.connection.getTokenAccountBalance(userAccountB);
+
+    await program.methods
+      .swapTokensForExactTokens(
+        true, // swap_a (swap token A for token B)
+        new anchor.BN(outputAmount),
+        new anchor.BN(maxInput),
+        null // no deadline
+      )
+      .accountsPartial({
+        amm: ammPda,
+        pool: poolPda,
+        observations: null,
+        poolAuthority: poolAuthority,
+        trader: user.publicKey,
+        mintA: mintA,
+        mintB: mintB,
+        poolAccountA: poolAccountA,
+        poolAccountB: poolAccountB,
+        traderAccountA: userAccountA,
+        traderAccountB: userAccountB,
+        payer: payer.publicKey,
+        tokenProgramA: TOKEN_PROGRAM_ID,
+        tokenProgramB: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, payer])
+      .rpc();
+
+    const userBalanceAAfter = await provider.connection.getTokenAccountBalance(userAccountA);
+    const userBalanceBAfter = await provider.connection.getTokenAccountBalance(userAccountB);
+
+    // Exactly the requested output is received, for no more than the cap
+    const spent = Number(userBalanceABefore.value.amount) - Number(userBalanceAAfter.value.amount);
+    expect(Number(userBalanceBAfter.value.amount) - Number(userBalanceBBefore.value.amount)).toEqual(outputAmount);
+    expect(spent).toBeGreaterThan(0);
+    expect(spent).toBeLessThanOrEqual(maxInput);
+  });
+
+  it("Rejects an exact-output swap above the input cap", async () => {
+    await expect(
+      program.methods
+        .swapTokensForExactTokens(true, new anchor.BN(500000), new anchor.BN(1), null)
+        .accountsPartial({
+          amm: ammPda,
+          pool: poolPda,
+          observations: null,
+          poolAuthority: poolAuthority,
+          trader: user.publicKey,
+          mintA: mintA,
+          mintB: mintB,
+          poolAccountA: poolAccountA,
+          poolAccountB: poolAccountB,
+          traderAccountA: userAccountA,
+          traderAccountB: userAccountB,
+          payer: payer.publicKey,
+          tokenProgramA: TOKEN_PROGRAM_ID,
+          tokenProgramB: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user, payer])
+        .rpc()
+    ).rejects.toThrow(/InputTooLarge/);
+  });
+
+  it("Updates the fee as admin", async () => {
+    await program.methods
+      .updateFee(250)
+      .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    let ammAccount = await program.account.amm.fetch(ammPda);
+    expect(ammAccount.fee).toEqual(250);
+
+    await expect(
+      program.methods
+        .updateFee(10000)
+        .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+        .signers([admin])
+        .rpc()
+    ).rejects.toThrow(/InvalidFee/);
+
+    await program.methods
+      .updateFee(300)
+      .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    ammAccount = await program.account.amm.fetch(ammPda);
+    expect(ammAccount.fee).toEqual(300);
+  });
+
+  it("Rejects fee updates from a non-admin", async () => {
+    await expect(
+      program.methods
+        .updateFee(0)
+        .accountsPartial({ amm: ammPda, admin: user.publicKey })
+        .signers([user])
+        .rpc()
+    ).rejects.toThrow(/ConstraintHasOne/);
+  });
+
+  it("Rejects admin proposals from a non-admin", async () => {
+    await expect(
+      program.methods
+        .proposeAdmin(user.publicKey)
+        .accountsPartial({ amm: ammPda, admin: user.publicKey })
+        .signers([user])
+        .rpc()
+    ).rejects.toThrow(/ConstraintHasOne/);
+  });
+
+  it("Transfers the admin in two steps", async () => {
+    await program.methods
+      .proposeAdmin(user.publicKey)
+      .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    let ammAccount = await program.account.amm.fetch(ammPda);
+    expect(ammAccount.admin.toString()).toEqual(admin.publicKey.toString());
+    expect(ammAccount.pendingAdmin.toString()).toEqual(user.publicKey.toString());
+
+    // Only the proposed admin can accept
+    await expect(
+      program.methods
+        .acceptAdmin()
+        .accountsPartial({ amm: ammPda, pendingAdmin: payer.publicKey })
+        .signers([payer])
+        .rpc()
+    ).rejects.toThrow(/ConstraintHasOne/);
+
+    await program.methods
+      .acceptAdmin()
+      .accountsPartial({ amm: ammPda, pendingAdmin: user.publicKey })
+      .signers([user])
+      .rpc();
+
+    ammAccount = await program.account.amm.fetch(ammPda);
+    expect(ammAccount.admin.toString()).toEqual(user.publicKey.toString());
+    expect(ammAccount.pendingAdmin.toString()).toEqual(PublicKey.default.toString());
+
+    // Hand the AMM back so the remaining tests keep the original admin
+    await program.methods
+      .proposeAdmin(admin.publicKey)
+      .accountsPartial({ amm: ammPda, admin: user.publicKey })
+      .signers([user])
+      .rpc();
+    await program.methods
+      .acceptAdmin()
+      .accountsPartial({ amm: ammPda, pendingAdmin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    ammAccount = await program.account.amm.fetch(ammPda);
+    expect(ammAccount.admin.toString()).toEqual(admin.publicKey.toString());
+  });
+
+  it("Rejects status changes from a non-admin", async () => {
+    await expect(
+      program.methods
+        .setPoolStatus({ frozen: {} })
+        .accountsPartial({ amm: ammPda, pool: poolPda, admin: user.publicKey })
+        .signers([user])
+        .rpc()
+    ).rejects.toThrow(/ConstraintHasOne/);
+
+    await expect(
+      program.methods
+        .setAmmStatus({ frozen: {} })
+        .accountsPartial({ amm: ammPda, admin: user.publicKey })
+        .signers([user])
+        .rpc()
+    ).rejects.toThrow(/ConstraintHasOne/);
+  });
+
+  it("Only allows withdrawals in withdraw-only mode", async () => {
+    await program.methods
+      .setPoolStatus({ withdrawOnly: {} })
+      .accountsPartial({ amm: ammPda, pool: poolPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    await expect(swap(true, 1000000, 1, mainPool())).rejects.toThrow(/WithdrawOnly/);
+    await expect(deposit(1000000, 1000000, mainPool())).rejects.toThrow(/WithdrawOnly/);
+    await withdraw(1000, mainPool());
+
+    await program.methods
+      .setPoolStatus({ active: {} })
+      .accountsPartial({ amm: ammPda, pool: poolPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Blocks everything when the AMM is frozen", async () => {
+    await program.methods
+      .setAmmStatus({ frozen: {} })
+      .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    await expect(swap(true, 1000000, 1, mainPool())).rejects.toThrow(/Frozen/);
+    await expect(deposit(1000000, 1000000, mainPool())).rejects.toThrow(/Frozen/);
+    await expect(withdraw(1000, mainPool())).rejects.toThrow(/Frozen/);
+
+    await program.methods
+      .setAmmStatus({ active: {} })
+      .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    await swap(true, 1000000, 1, mainPool());
+  });
+
+  it("Rejects protocol fee changes from a non-admin", async () => {
+    await expect(
+      program.methods
+        .setProtocolFee(5000, user.publicKey)
+        .accountsPartial({ amm: ammPda, admin: user.publicKey })
+        .signers([user])
+        .rpc()
+    ).rejects.toThrow(/ConstraintHasOne/);
+  });
+
+  it("Collects protocol fees to the treasury", async () => {
+    const treasury = Keypair.generate().publicKey;
+    await program.methods
+      .setProtocolFee(5000, treasury) // half of the swap fee
+      .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    // 1 token in at a 3% fee leaves 0.015 token for the protocol
+    await swap(true, 1000000, 1, mainPool());
+    let poolAccount = await program.account.pool.fetch(poolPda);
+    expect(poolAccount.protocolFeesA.toNumber()).toEqual(15000);
+
+    await collectProtocolFees(treasury);
+
+    const treasuryAccountA = await getAssociatedTokenAddress(mintA, treasury, true);
+    const treasuryBalanceA = await provider.connection.getTokenAccountBalance(treasuryAccountA);
+    expect(Number(treasuryBalanceA.value.amount)).toEqual(15000);
+    poolAccount = await program.account.pool.fetch(poolPda);
+    expect(poolAccount.protocolFeesA.toNumber()).toEqual(0);
+    expect(poolAccount.protocolFeesB.toNumber()).toEqual(0);
+
+    await program.methods
+      .setProtocolFee(0, admin.publicKey)
+      .accountsPartial({ amm: ammPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Rejects protocol fee collection from a frozen pool", async () => {
+    await program.methods
+      .setPoolStatus({ frozen: {} })
+      .accountsPartial({ amm: ammPda, pool: poolPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    await expect(collectProtocolFees(admin.publicKey)).rejects.toThrow(/Frozen/);
+
+    await program.methods
+      .setPoolStatus({ active: {} })
+      .accountsPartial({ amm: ammPda, pool: poolPda, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+  });
+
+  async function collectProtocolFees(treasury: PublicKey) {
+    return program.methods
+      .collectProtocolFees()
+      .accountsPartial({
+        amm: ammPda,
+        pool: poolPda,
+        poolAuthority: poolAuthority,
+        mintA: mintA,
+        mintB: mintB,
+        poolAccountA: poolAccountA,
+        poolAccountB: poolAccountB,
+        treasury: treasury,
+        treasuryAccountA: await getAssociatedTokenAddress(mintA, treasury, true),
+        treasuryAccountB: await getAssociatedTokenAddress(mintB, treasury, true),
+        payer: payer.publicKey,
+        tokenProgramA: TOKEN_PROGRAM_ID,
+        tokenProgramB: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([payer])
+      .rpc();
+  }
+
+  it("Emits a Swapped event with the post-trade reserves", async () => {
+    const signature = await swap(true, 1000000, 1, mainPool());
+    const events = await parseEvents(signature);
+    const swapped = events.find((event) => event.name === "swapped");
+
+    expect(swapped).toBeDefined();
+    expect(swapped!.data.pool.toString()).toEqual(poolPda.toString());
+    expect(swapped!.data.swapA).toBe(true);
+    expect(swapped!.data.input.toNumber()).toEqual(1000000);
+    expect(swapped!.data.fee.toNumber()).toEqual(30000);
+
+    const poolBalanceA = await provider.connection.getTokenAccountBalance(poolAccountA);
+    expect(swapped!.data.reserveA.toString()).toEqual(poolBalanceA.value.amount);
+  });
+
+  it("Emits liquidity events with the amounts moved and the reserves after", async () => {
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(mintLiquidity, user.publicKey, true);
+
+    let poolBalanceA = await balance(poolAccountA);
+    let poolBalanceB = await balance(poolAccountB);
+    let liquidityBalance = await balance(depositorAccountLiquidity);
+    const deposited = (await parseEvents(await deposit(2000000, 3000000, mainPool()))).find(
+      (event) => event.name === "liquidityDeposited"
+    );
+    let poolAccount = await program.account.pool.fetch(poolPda);
+
+    expect(deposited).toBeDefined();
+    expect(deposited!.data.pool.toString()).toEqual(poolPda.toString());
+    expect(deposited!.data.depositor.toString()).toEqual(user.publicKey.toString());
+    expect(deposited!.data.amountA.toNumber()).toEqual((await balance(poolAccountA)) - poolBalanceA);
+    expect(deposited!.data.amountB.toNumber()).toEqual((await balance(poolAccountB)) - poolBalanceB);
+    expect(deposited!.data.liquidity.toNumber()).toEqual((await balance(depositorAccountLiquidity)) - liquidityBalance);
+    expect(deposited!.data.reserveA.toString()).toEqual(poolAccount.reserveA.toString());
+    expect(deposited!.data.reserveB.toString()).toEqual(poolAccount.reserveB.toString());
+
+    poolBalanceA = await balance(poolAccountA);
+    poolBalanceB = await balance(poolAccountB);
+    liquidityBalance = await balance(depositorAccountLiquidity);
+    const withdrawn = (await parseEvents(await withdraw(1000000, mainPool()))).find((event) => event.name === "liquidityWithdrawn");
+    poolAccount = await program.account.pool.fetch(poolPda);
+
+    expect(withdrawn).toBeDefined();
+    expect(withdrawn!.data.pool.toString()).toEqual(poolPda.toString());
+    expect(withdrawn!.data.depositor.toString()).toEqual(user.publicKey.toString());
+    expect(withdrawn!.data.amountA.toNumber()).toEqual(poolBalanceA - (await balance(poolAccountA)));
+    expect(withdrawn!.data.amountB.toNumber()).toEqual(poolBalanceB - (await balance(poolAccountB)));
+    expect(withdrawn!.data.liquidity.toNumber()).toEqual(liquidityBalance - (await balance(depositorAccountLiquidity)));
+    expect(withdrawn!.data.liquidity.toNumber()).toEqual(1000000);
+    expect(withdrawn!.data.reserveA.toString()).toEqual(poolAccount.reserveA.toString());
+    expect(withdrawn!.data.reserveB.toString()).toEqual(poolAccount.reserveB.toString());
+  });
+
+  async function parseEvents(signature: string) {
+    await confirmTransaction(provider.connection, signature);
+    const transaction = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    return Array.from(parser.parseLogs(transaction?.meta?.logMessages ?? []));
+  }
+
+  it("Trades in a pool mixing SPL Token and Token-2022 mints", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+    expect(await balance(fixture.poolAccountA)).toEqual(100000000);
+    expect(await balance(fixture.poolAccountB)).toEqual(100000000);
+
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await swap(true, 1000000, 1, fixture);
+    expect(await balance(fixture.poolAccountA)).toEqual(101000000);
+    expect(await balance(fixture.userAccountB)).toBeGreaterThan(userBalanceBBefore);
+  });
+
+  it("Trades in a pool of two Token-2022 mints", async () => {
+    const fixture = await createPoolFixture(TOKEN_2022_PROGRAM_ID, TOKEN_2022_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await swap(true, 1000000, 1, fixture);
+    expect(await balance(fixture.userAccountB)).toBeGreaterThan(userBalanceBBefore);
+  });
+
+  it("Quotes swaps on amounts net of Token-2022 transfer fees", async () => {
+    // Mint B withholds 1% of every transfer
+    const mintB = await createTransferFeeMint(100);
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, { mintB });
+    await deposit(100000000, 100000000, fixture);
+
+    const reserveA = await balance(fixture.poolAccountA);
+    const reserveB = await balance(fixture.poolAccountB);
+    expect(reserveA).toEqual(100000000);
+    expect(reserveB).toEqual(99000000);
+
+    // 3% swap fee, then 1% transfer fee on the way out of the pool
+    const input = 1000000;
+    const taxedInput = input - Math.floor((input * 300) / 10000);
+    const output = Math.floor((taxedInput * reserveB) / (reserveA + taxedInput));
+    const received = output - Math.ceil((output * 100) / 10000);
+
+    // Slippage protects what the trader receives, not what the pool sends
+    await expect(swap(true, input, output, fixture)).rejects.toThrow(/OutputTooSmall/);
+
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await swap(true, input, received, fixture);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore + received);
+    expect(await balance(fixture.poolAccountB)).toEqual(reserveB - output);
+  });
+
+  it("Routes a swap through two pools", async () => {
+    // B/C pool next to the main A/B pool
+    const second = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, { mintA: mintB });
+    await deposit(50000000, 50000000, second);
+
+    const balanceABefore = await balance(userAccountA);
+    const balanceBBefore = await balance(userAccountB);
+    const balanceCBefore = await balance(second.userAccountB);
+
+    await swapRoute(1000000, 1, [mainPool(), second]);
+
+    expect(await balance(userAccountA)).toEqual(balanceABefore - 1000000);
+    // The intermediate token B never passes through the trader
+    expect(await balance(userAccountB)).toEqual(balanceBBefore);
+    expect(await balance(second.userAccountB)).toBeGreaterThan(balanceCBefore);
+  });
+
+  it("Rejects routes below the minimum output or with disconnected pools", async () => {
+    const second = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, { mintA: mintB });
+    await deposit(50000000, 50000000, second);
+
+    await expect(swapRoute(1000000, 1000000, [mainPool(), second])).rejects.toThrow(/OutputTooSmall/);
+    // Token B, bought from the first pool, does not trade in the second one
+    const unrelated = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(50000000, 50000000, unrelated);
+    await expect(swapRoute(1000000, 1, [mainPool(), unrelated])).rejects.toThrow(/InvalidRoute/);
+  });
+
+  it("Rejects deposits minting less liquidity than quoted", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+
+    // Quote a balanced deposit, then let a trade move the price before it lands
+    const supply = Number((await getMint(provider.connection, fixture.mintLiquidity)).supply);
+    const quote = Math.floor((10000000 * supply) / (await balance(fixture.poolAccountA)));
+    await swap(true, 20000000, 1, fixture);
+
+    await expect(deposit(10000000, 10000000, fixture, quote)).rejects.toThrow(/LiquidityTooSmall/);
+  });
+
+  it("Swaps token A for token B at the constant-product price", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 200000000, fixture);
+
+    const input = 1000000;
+    const taxedInput = input - Math.floor((input * 300) / 10000);
+    const output = Math.floor((taxedInput * 200000000) / (100000000 + taxedInput));
+    const userBalanceABefore = await balance(fixture.userAccountA);
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+
+    await swap(true, input, output, fixture);
+
+    expect(await balance(fixture.userAccountA)).toEqual(userBalanceABefore - input);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore + output);
+    expect(await balance(fixture.poolAccountA)).toEqual(100000000 + input);
+    expect(await balance(fixture.poolAccountB)).toEqual(200000000 - output);
+  });
+
+  it("Swaps token B for token A at the constant-product price", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 200000000, fixture);
+
+    const input = 2000000;
+    const taxedInput = input - Math.floor((input * 300) / 10000);
+    const output = Math.floor((taxedInput * 100000000) / (200000000 + taxedInput));
+    const userBalanceABefore = await balance(fixture.userAccountA);
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+
+    await swap(false, input, output, fixture);
+
+    // The trader pays token B into the pool and receives token A from it
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore - input);
+    expect(await balance(fixture.userAccountA)).toEqual(userBalanceABefore + output);
+    expect(await balance(fixture.poolAccountB)).toEqual(200000000 + input);
+    expect(await balance(fixture.poolAccountA)).toEqual(100000000 - output);
+    await expect(swap(false, input, output * 2, fixture)).rejects.toThrow(/OutputTooSmall/);
+  });
+
+  it("Trades in a pool whose reserve product exceeds 64 bits", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    // 1000 tokens of 9 decimals on each side
+    await mintTo(provider.connection, payer, fixture.mintA, fixture.userAccountA, payer.publicKey, BigInt(2000000000000));
+    await mintTo(provider.connection, payer, fixture.mintB, fixture.userAccountB, payer.publicKey, BigInt(2000000000000));
+    await deposit(1000000000000, 1000000000000, fixture);
+
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await swap(true, 1000000000, 1, fixture);
+    const taxedInput = 1000000000 - Math.floor((1000000000 * 300) / 10000);
+    const output = (BigInt(taxedInput) * BigInt(1000000000000)) / BigInt(1000000000000 + taxedInput);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore + Number(output));
+  });
+
+  it("Deposits at the pool ratio and leaves the excess with the depositor", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    // Pool priced at 2 B per A
+    await deposit(100000000, 200000000, fixture);
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(fixture.mintLiquidity, user.publicKey, true);
+    const supply = Number((await getMint(provider.connection, fixture.mintLiquidity)).supply);
+
+    const userBalanceABefore = await balance(fixture.userAccountA);
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    const liquidityBefore = await balance(depositorAccountLiquidity);
+
+    // Only 2 B are needed next to 1 A, the other 8 B are not taken
+    await deposit(1000000, 10000000, fixture);
+
+    expect(await balance(fixture.userAccountA)).toEqual(userBalanceABefore - 1000000);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore - 2000000);
+    expect(await balance(fixture.poolAccountA)).toEqual(101000000);
+    expect(await balance(fixture.poolAccountB)).toEqual(202000000);
+    expect(await balance(depositorAccountLiquidity)).toEqual(liquidityBefore + Math.floor((1000000 * supply) / 100000000));
+  });
+
+  it("Rejects dust deposits that would mint no liquidity", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 200000000, fixture);
+
+    // 1 B only matches half a unit of A, which mints less than one liquidity token
+    await expect(deposit(1, 1, fixture)).rejects.toThrow(/DepositTooSmall/);
+  });
+
+  it("Returns a deposit in full when its liquidity is withdrawn", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 300000000, fixture);
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(fixture.mintLiquidity, user.publicKey, true);
+
+    const userBalanceABefore = await balance(fixture.userAccountA);
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    const liquidityBefore = await balance(depositorAccountLiquidity);
+
+    await deposit(12345678, 37037034, fixture);
+    await withdraw((await balance(depositorAccountLiquidity)) - liquidityBefore, fixture);
+
+    // Payouts round down in favor of the pool, by at most a few units
+    const lostA = userBalanceABefore - (await balance(fixture.userAccountA));
+    const lostB = userBalanceBBefore - (await balance(fixture.userAccountB));
+    expect(lostA).toBeGreaterThanOrEqual(0);
+    expect(lostA).toBeLessThanOrEqual(1);
+    expect(lostB).toBeGreaterThanOrEqual(0);
+    expect(lostB).toBeLessThanOrEqual(3);
+
+    // Withdrawing everything else leaves only the share of the locked liquidity in the pool
+    await withdraw(await balance(depositorAccountLiquidity), fixture);
+    const lockedA = await balance(fixture.poolAccountA);
+    const lockedB = await balance(fixture.poolAccountB);
+    expect(lockedA).toBeGreaterThan(0);
+    expect(lockedB).toBeGreaterThan(0);
+    expect(await balance(fixture.userAccountA)).toEqual(userBalanceABefore + 100000000 - lockedA);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore + 300000000 - lockedB);
+  });
+
+  it("Mints the minimum liquidity to the pool's lock account on the first deposit", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    expect(await balance(fixture.liquidityLock)).toEqual(0);
+
+    await deposit(100000000, 100000000, fixture);
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(fixture.mintLiquidity, user.publicKey, true);
+    const supply = Number((await getMint(provider.connection, fixture.mintLiquidity)).supply);
+    expect(await balance(fixture.liquidityLock)).toEqual(100);
+    expect(await balance(depositorAccountLiquidity)).toEqual(100000000 - 100);
+    expect(supply).toEqual(100000000);
+
+    // Later deposits leave the lock untouched
+    await deposit(10000000, 10000000, fixture);
+    expect(await balance(fixture.liquidityLock)).toEqual(100);
+  });
+
+  it("Rejects withdrawals paying out less than quoted", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(fixture.mintLiquidity, user.publicKey, true);
+
+    // Quote a withdrawal, then let a trade drain token B before it lands
+    const liquidity = await balance(depositorAccountLiquidity);
+    const supply = Number((await getMint(provider.connection, fixture.mintLiquidity)).supply);
+    const quoteB = Math.floor((liquidity * (await balance(fixture.poolAccountB))) / supply);
+    await swap(true, 20000000, 1, fixture);
+
+    await expect(withdraw(liquidity, fixture, 0, quoteB)).rejects.toThrow(/WithdrawalTooSmall/);
+  });
+
+  it("Checks withdrawal minimums net of Token-2022 transfer fees", async () => {
+    // Mint B withholds 1% of every transfer
+    const mintB = await createTransferFeeMint(100);
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, { mintB });
+    await deposit(100000000, 100000000, fixture);
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(fixture.mintLiquidity, user.publicKey, true);
+
+    // The pool sends its pro-rata share, but the depositor receives 1% less of it
+    const liquidity = await balance(depositorAccountLiquidity);
+    const supply = Number((await getMint(provider.connection, fixture.mintLiquidity)).supply);
+    const sentB = Math.floor((liquidity * (await balance(fixture.poolAccountB))) / supply);
+
+    await expect(withdraw(liquidity, fixture, 0, sentB)).rejects.toThrow(/WithdrawalTooSmall/);
+  });
+
+  it("Prices from tracked reserves, ignoring tokens sent straight to the pool", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+
+    // Donating to the pool account does not move the price
+    await transfer(provider.connection, payer, fixture.userAccountA, fixture.poolAccountA, user, 50000000);
+    let poolAccount = await program.account.pool.fetch(fixture.pool);
+    expect(poolAccount.reserveA.toNumber()).toEqual(100000000);
+    expect(poolAccount.reserveB.toNumber()).toEqual(100000000);
+
+    const input = 1000000;
+    const taxedInput = input - Math.ceil((input * 300) / 10000);
+    const output = Math.floor((taxedInput * 100000000) / (100000000 + taxedInput));
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await swap(true, input, output, fixture);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore + output);
+
+    poolAccount = await program.account.pool.fetch(fixture.pool);
+    expect(poolAccount.reserveA.toNumber()).toEqual(100000000 + input);
+    expect(poolAccount.reserveB.toNumber()).toEqual(100000000 - output);
+  });
+
+  it("Skims the surplus of the pool accounts to any recipient", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+    await transfer(provider.connection, payer, fixture.userAccountB, fixture.poolAccountB, user, 2000000);
+
+    // Anyone can skim, here to the payer's accounts
+    const recipientAccountA = (await getOrCreateAssociatedTokenAccount(provider.connection, payer, fixture.mintA, payer.publicKey)).address;
+    const recipientAccountB = (await getOrCreateAssociatedTokenAccount(provider.connection, payer, fixture.mintB, payer.publicKey)).address;
+    await program.methods
+      .skim()
+      .accountsPartial({
+        amm: ammPda,
+        pool: fixture.pool,
+        poolAuthority: fixture.poolAuthority,
+        mintA: fixture.mintA,
+        mintB: fixture.mintB,
+        poolAccountA: fixture.poolAccountA,
+        poolAccountB: fixture.poolAccountB,
+        recipientAccountA,
+        recipientAccountB,
+        tokenProgramA: fixture.tokenProgramA,
+        tokenProgramB: fixture.tokenProgramB,
+      })
+      .rpc();
+
+    expect(await balance(recipientAccountA)).toEqual(0);
+    expect(await balance(recipientAccountB)).toEqual(2000000);
+    expect(await balance(fixture.poolAccountB)).toEqual(100000000);
+  });
+
+  it("Syncs the surplus into the reserves as admin only", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+    await transfer(provider.connection, payer, fixture.userAccountA, fixture.poolAccountA, user, 3000000);
+
+    const sync = (signer: Keypair) =>
+      program.methods
+        .sync()
+        .accountsPartial({
+          amm: ammPda,
+          pool: fixture.pool,
+          poolAuthority: fixture.poolAuthority,
+          mintA: fixture.mintA,
+          mintB: fixture.mintB,
+          poolAccountA: fixture.poolAccountA,
+          poolAccountB: fixture.poolAccountB,
+          admin: signer.publicKey,
+          tokenProgramA: fixture.tokenProgramA,
+          tokenProgramB: fixture.tokenProgramB,
+        })
+        .signers([signer])
+        .rpc();
+
+    await expect(sync(user)).rejects.toThrow(/ConstraintHasOne/);
+
+    // Frozen pools keep their reserves, surplus included
+    await program.methods
+      .setPoolStatus({ frozen: {} })
+      .accountsPartial({ amm: ammPda, pool: fixture.pool, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    await expect(sync(admin)).rejects.toThrow(/Frozen/);
+    await program.methods
+      .setPoolStatus({ active: {} })
+      .accountsPartial({ amm: ammPda, pool: fixture.pool, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    await sync(admin);
+
+    const poolAccount = await program.account.pool.fetch(fixture.pool);
+    expect(poolAccount.reserveA.toNumber()).toEqual(103000000);
+    expect(poolAccount.reserveB.toNumber()).toEqual(100000000);
+  });
+
+  it("Lends pool reserves within a transaction for a fee paid to LPs", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID);
+    await deposit(100000000, 100000000, fixture);
+
+    const accounts = {
+      amm: ammPda,
+      pool: fixture.pool,
+      poolAuthority: fixture.poolAuthority,
+      mintA: fixture.mintA,
+      mintB: fixture.mintB,
+      poolAccountA: fixture.poolAccountA,
+      poolAccountB: fixture.poolAccountB,
+      borrower: user.publicKey,
+      borrowerAccountA: fixture.userAccountA,
+      borrowerAccountB: fixture.userAccountB,
+      tokenProgramA: fixture.tokenProgramA,
+      tokenProgramB: fixture.tokenProgramB,
+    };
+    const borrow = (amountA: number, amountB: number) =>
+      program.methods
+        .flashBorrow(new anchor.BN(amountA), new anchor.BN(amountB))
+        .accountsPartial({ ...accounts, instructions: SYSVAR_INSTRUCTIONS_PUBKEY })
+        .instruction();
+    const repay = () => program.methods.flashRepay().accountsPartial(accounts).instruction();
+    const send = (...instructions: TransactionInstruction[]) =>
+      sendAndConfirmTransaction(provider.connection, new Transaction().add(...instructions), [payer, user]);
+
+    // Borrowing needs a repayment later in the same transaction
+    await expect(send(await borrow(10000000, 0))).rejects.toThrow(/FlashLoanNotRepaid/);
+    await expect(send(await repay())).rejects.toThrow(/NoFlashLoan/);
+
+    // The pool is locked while the loan is open
+    const swapDuringLoan = await swapMethod(true, 1000000, 1, fixture, null).instruction();
+    await expect(send(await borrow(10000000, 0), swapDuringLoan, await repay())).rejects.toThrow(/FlashLoanOpen/);
+    await expect(send(await borrow(10000000, 0), await borrow(1, 0), await repay())).rejects.toThrow(/FlashLoanOpen/);
+    await expect(send(await borrow(100000001, 0), await repay())).rejects.toThrow(/InsufficientLiquidity/);
+
+    // 3% of 10 and 5 tokens, paid on top of the loan and added to the reserves
+    const userBalanceABefore = await balance(fixture.userAccountA);
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await send(await borrow(10000000, 5000000), await repay());
+
+    expect(await balance(fixture.userAccountA)).toEqual(userBalanceABefore - 300000);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore - 150000);
+    const poolAccount = await program.account.pool.fetch(fixture.pool);
+    expect(poolAccount.flashLoan).toBeNull();
+    expect(poolAccount.reserveA.toNumber()).toEqual(100300000);
+    expect(poolAccount.reserveB.toNumber()).toEqual(100150000);
+
+    // The pool is unlocked again
+    await swap(true, 1000000, 1, fixture);
+  });
+
+  it("Derives the canonical pool for an unordered pair of mints", async () => {
+    expect(sortMints(mintB, mintA)).toEqual([mintA, mintB]);
+    expect(getPoolAddress(ammPda, mintB, mintA, program.programId)).toEqual(poolPda);
+    expect(getPoolAddress(ammPda, mintA, mintB, program.programId)).toEqual(poolPda);
+    expect(() => getPoolAddress(ammPda, mintA, mintA, program.programId)).toThrow();
+  });
+
+  it("Rejects pools with unordered or identical mints", async () => {
+    // The B/A pool would split the liquidity of the A/B pool
+    const reversed = { ...mainPool(), mintA: mintB, mintB: mintA, ...poolAddresses(mintB, mintA) };
+    reversed.poolAccountA = await getAssociatedTokenAddress(mintB, reversed.poolAuthority, true);
+    reversed.poolAccountB = await getAssociatedTokenAddress(mintA, reversed.poolAuthority, true);
+    await expect(createPool(reversed)).rejects.toThrow(/InvalidMint/);
+
+    const identical = { ...mainPool(), mintA: mintB, mintB: mintB, ...poolAddresses(mintB, mintB) };
+    identical.poolAccountA = await getAssociatedTokenAddress(mintB, identical.poolAuthority, true);
+    identical.poolAccountB = identical.poolAccountA;
+    await expect(createPool(identical)).rejects.toThrow(/InvalidMint/);
+  });
+
+  it("Swaps a stable pool at almost one for one", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { amplification: 100 });
+    const poolAccount = await program.account.pool.fetch(fixture.pool);
+    expect(poolAccount.curveType).toEqual({ stable: {} });
+    expect(new anchor.BN(poolAccount.curveParams.slice(0, 8), "le").toNumber()).toEqual(100);
+    await deposit(100000000, 100000000, fixture);
+
+    // Constant product would pay 960_681 for 1% of the reserves
+    const input = 1000000;
+    const taxedInput = input - Math.floor((input * 300) / 10000);
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await swap(true, input, taxedInput - 1000, fixture);
+
+    const output = (await balance(fixture.userAccountB)) - userBalanceBBefore;
+    expect(output).toBeGreaterThan(taxedInput - 1000);
+    expect(output).toBeLessThan(taxedInput);
+    expect(await balance(fixture.poolAccountB)).toEqual(100000000 - output);
+  });
+
+  it("Takes imbalanced deposits into a stable pool for a fee", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { amplification: 100 });
+    await deposit(100000000, 100000000, fixture);
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(fixture.mintLiquidity, user.publicKey, true);
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    const liquidityBefore = await balance(depositorAccountLiquidity);
+
+    // Unlike constant product pools, the whole one-sided deposit is taken
+    await deposit(2000000, 0, fixture);
+
+    expect(await balance(fixture.poolAccountA)).toEqual(102000000);
+    expect(await balance(fixture.userAccountB)).toEqual(userBalanceBBefore);
+    const liquidity = (await balance(depositorAccountLiquidity)) - liquidityBefore;
+    // A balanced deposit of the same value would mint 2_000_000
+    expect(liquidity).toBeGreaterThan(1900000);
+    expect(liquidity).toBeLessThan(2000000);
+  });
+
+  it("Rejects stable pools with an out of range amplification", async () => {
+    await expect(createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { amplification: 1000001 })).rejects.toThrow(/InvalidAmplification/);
+  });
+
+  it("Swaps an 80/20 pool at the weighted price", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { weights: [8000, 2000] });
+    const poolAccount = await program.account.pool.fetch(fixture.pool);
+    expect(poolAccount.curveType).toEqual({ weighted: {} });
+    expect(poolAccount.curveParams.slice(0, 4)).toEqual([0x40, 0x1f, 0xd0, 0x07]);
+    // Both tokens are worth the same with 80% of the value in A
+    await deposit(80000000, 20000000, fixture);
+
+    const input = 1000000;
+    const taxedInput = input - Math.floor((input * 300) / 10000);
+    const reference = 20000000 * (1 - Math.pow(80000000 / (80000000 + taxedInput), 8000 / 2000));
+    const userBalanceBBefore = await balance(fixture.userAccountB);
+    await swap(true, input, 1, fixture);
+
+    // Constant product over the same reserves would only pay 239_594
+    const output = (await balance(fixture.userAccountB)) - userBalanceBBefore;
+    expect(output).toBeLessThanOrEqual(Math.floor(reference));
+    expect(output).toBeGreaterThanOrEqual(Math.floor(reference) - 2);
+  });
+
+  it("Mints the weighted geometric mean of a weighted pool's first deposit", async () => {
+    const fixture = await createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { weights: [8000, 2000] });
+    await deposit(80000000, 20000000, fixture);
+
+    const depositorAccountLiquidity = await getAssociatedTokenAddress(fixture.mintLiquidity, user.publicKey, true);
+    const reference = Math.pow(80000000, 0.8) * Math.pow(20000000, 0.2) - 100;
+    expect(await balance(depositorAccountLiquidity)).toBeLessThanOrEqual(Math.floor(reference));
+    expect(await balance(depositorAccountLiquidity)).toBeGreaterThanOrEqual(Math.floor(reference) - 2);
+  });
+
+  it("Rejects weighted pools with invalid weights", async () => {
+    await expect(createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { weights: [9000, 2000] })).rejects.toThrow(/InvalidWeights/);
+    await expect(createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { weights: [9950, 50] })).rejects.toThrow(/InvalidWeights/);
+  });
+
+  it("Rejects curve parameters the curve type does not take", async () => {
+    await expect(createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { params: [1] })).rejects.toThrow(/InvalidCurveParams/);
+    await expect(
+      createPoolFixture(TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, {}, { amplification: 100, params: [100, 0, 0, 0, 0, 0, 0, 0, 1] })
+    ).rejects.toThrow(/InvalidCurveParams/);
+  });
+
+  function mainPool(): PoolFixture {
+    return {
+      mintA,
+      mintB,
+      tokenProgramA: TOKEN_PROGRAM_ID,
+      tokenProgramB: TOKEN_PROGRAM_ID,
+      pool: poolPda,
+      poolAuthority,
+      mintLiquidity,
+      liquidityLock,
+      poolAccountA,
+      poolAccountB,
+      userAccountA,
+      userAccountB,
+    };
+  }
+
+  // Creates a Token-2022 mint withholding `feeBasisPoints` of every transfer
+  async function createTransferFeeMint(feeBasisPoints: number) {
+    const mint = Keypair.generate();
+    const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    const lamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    const transaction = new Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: payer.publicKey,
+        newAccountPubkey: mint.publicKey,
+        space: mintLen,
+        lamports,
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      createInitializeTransferFeeConfigInstruction(
+        mint.publicKey,
+        payer.publicKey,
+        payer.publicKey,
+        feeBasisPoints,
+        BigInt(1000000000),
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createInitializeMintInstruction(mint.publicKey, 6, payer.publicKey, null, TOKEN_2022_PROGRAM_ID)
+    );
+    await sendAndConfirmTransaction(provider.connection, transaction, [payer, mint]);
+    return mint.publicKey;
+  }
+
+  async function balance(account: PublicKey) {
+    const result = await provider.connection.getTokenAccountBalance(account);
+    return Number(result.value.amount);
+  }
+
   // Helper function to wait for transaction confirmation
   async function confirmTransaction(connection: any, signature: string) {
     const latestBlockhash = await connection.getLatestBlockhash();
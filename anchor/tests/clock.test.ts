@@ -0,0 +1,245 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
+import { BankrunProvider, startAnchor } from "anchor-bankrun";
+import { Clock, ProgramTestContext } from "solana-bankrun";
+import { Amm, AmmIDL } from "../src/amm-exports";
+import { PoolFixture, ammHelpers } from "./helpers";
+
+// Instructions that read the `Clock` sysvar, run in a local bank whose clock
+// the tests move by hand instead of waiting on a validator
+describe("AMM Program Clock Tests", () => {
+  const user = Keypair.generate();
+  const ammId = Keypair.generate().publicKey;
+
+  let context: ProgramTestContext;
+  let provider: BankrunProvider;
+  let program: Program<Amm>;
+  let payer: Keypair;
+  let ammPda: PublicKey;
+
+  const { createPoolFixture, createObservations, swap, swapExactOutput, swapRoute, deposit, withdraw } = ammHelpers(() => ({
+    provider,
+    program,
+    payer,
+    user,
+    amm: ammPda,
+    rent: async (space) => Number((await context.banksClient.getRent()).minimumBalance(BigInt(space))),
+  }));
+
+  beforeAll(async () => {
+    context = await startAnchor(
+      "",
+      [],
+      [
+        {
+          address: user.publicKey,
+          info: {
+            lamports: 10 * anchor.web3.LAMPORTS_PER_SOL,
+            data: Buffer.alloc(0),
+            owner: SystemProgram.programId,
+            executable: false,
+          },
+        },
+      ]
+    );
+    provider = new BankrunProvider(context);
+    program = new Program<Amm>(AmmIDL as Amm, provider);
+    payer = context.payer;
+
+    [ammPda] = PublicKey.findProgramAddressSync([ammId.toBuffer()], program.programId);
+    await program.methods
+      .createAmm(ammId, 300)
+      .accountsPartial({
+        payer: payer.publicKey,
+        admin: payer.publicKey,
+        amm: ammPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+  });
+
+  it("Accepts deadlines up to the current timestamp and rejects them one second later", async () => {
+    const fixture = await createPoolFixture();
+    await deposit(100000000, 100000000, fixture);
+
+    const now = await warp(0);
+    const deadline = { unixTimestamp: { 0: new anchor.BN(now.unixTimestamp.toString()) } };
+    await swap(true, 1000000, 1, fixture, deadline);
+    await swapExactOutput(true, 1000000, 2000000, fixture, deadline);
+    await swapRoute(1000000, 1, [fixture], deadline);
+    await deposit(1000000, 1000000, fixture, 0, deadline);
+    await withdraw(1000000, fixture, 0, 0, deadline);
+
+    await warp(1);
+    await expect(swap(true, 1000000, 1, fixture, deadline)).rejects.toThrow(/Expired/);
+    await expect(swapExactOutput(true, 1000000, 2000000, fixture, deadline)).rejects.toThrow(/Expired/);
+    await expect(swapRoute(1000000, 1, [fixture], deadline)).rejects.toThrow(/Expired/);
+    await expect(deposit(1000000, 1000000, fixture, 0, deadline)).rejects.toThrow(/Expired/);
+    await expect(withdraw(1000000, fixture, 0, 0, deadline)).rejects.toThrow(/Expired/);
+  });
+
+  it("Accepts deadlines up to the current slot and rejects them one slot later", async () => {
+    const fixture = await createPoolFixture();
+    await deposit(100000000, 100000000, fixture);
+
+    const now = await warp(0);
+    const deadline = { slot: { 0: new anchor.BN(now.slot.toString()) } };
+    await swap(true, 1000000, 1, fixture, deadline);
+    await swapExactOutput(true, 1000000, 2000000, fixture, deadline);
+    await swapRoute(1000000, 1, [fixture], deadline);
+    await deposit(1000000, 1000000, fixture, 0, deadline);
+    await withdraw(1000000, fixture, 0, 0, deadline);
+
+    await warp(0);
+    await expect(swap(true, 1000000, 1, fixture, deadline)).rejects.toThrow(/Expired/);
+    await expect(swapExactOutput(true, 1000000, 2000000, fixture, deadline)).rejects.toThrow(/Expired/);
+    await expect(swapRoute(1000000, 1, [fixture], deadline)).rejects.toThrow(/Expired/);
+    await expect(deposit(1000000, 1000000, fixture, 0, deadline)).rejects.toThrow(/Expired/);
+    await expect(withdraw(1000000, fixture, 0, 0, deadline)).rejects.toThrow(/Expired/);
+  });
+
+  it("Accumulates time-weighted prices across swaps and idle periods", async () => {
+    const fixture = await createPoolFixture();
+    await deposit(100000000, 200000000, fixture);
+
+    const start = await warp(0);
+    await updateOracle(fixture);
+    const initial = await readOracle(fixture);
+    expect(initial.timestamp).toEqual(start.unixTimestamp);
+
+    // An idle pool accumulates its spot price of 2 B per A every second
+    await warp(10);
+    await updateOracle(fixture);
+    const idle = await readOracle(fixture);
+    expect(idle.timestamp).toEqual(start.unixTimestamp + 10n);
+    expect(idle.priceA - initial.priceA).toEqual(10n * (2n << 64n));
+    expect(idle.priceB - initial.priceB).toEqual(10n * (1n << 63n));
+
+    // A swap accumulates the price it replaces before moving the reserves
+    await warp(15);
+    await swap(true, 10000000, 1, fixture);
+    const swapped = await readOracle(fixture);
+    expect(swapped.timestamp).toEqual(start.unixTimestamp + 25n);
+    expect(swapped.priceA - idle.priceA).toEqual(15n * (2n << 64n));
+
+    await warp(7);
+    await updateOracle(fixture);
+    const end = await readOracle(fixture);
+    expect(end.timestamp).toEqual(start.unixTimestamp + 32n);
+    expect(end.priceA - idle.priceA).toEqual(
+      15n * spotPrice(idle.reserveA, idle.reserveB) + 7n * spotPrice(swapped.reserveA, swapped.reserveB)
+    );
+    expect(end.priceB - idle.priceB).toEqual(
+      15n * spotPrice(idle.reserveB, idle.reserveA) + 7n * spotPrice(swapped.reserveB, swapped.reserveA)
+    );
+  });
+
+  it("Records pool accumulators on swaps and interpolates between them", async () => {
+    const fixture = await createPoolFixture();
+    await deposit(100000000, 400000000, fixture);
+    const start = await warp(0);
+    const observations = await createObservations(fixture);
+
+    // The pool holds 1 A for 4 B, with a geometric mean of 200000000, until a swap after 10 seconds
+    await warp(10);
+    await swap(true, 10000000, 1, { ...fixture, observations });
+    await warp(10);
+
+    const buffer = await program.account.observations.fetch(observations);
+    expect(buffer.pool).toEqual(fixture.pool);
+    expect(buffer.len).toEqual(2);
+    expect(buffer.index).toEqual(1);
+    const [first, second] = buffer.observations.map((observation) => ({
+      priceA: BigInt(observation.priceACumulative.toString()),
+      liquidity: BigInt(observation.liquidityCumulative.toString()),
+      timestamp: BigInt(observation.timestamp.toString()),
+    }));
+    expect(first.timestamp).toEqual(start.unixTimestamp);
+    expect(second.timestamp).toEqual(start.unixTimestamp + 10n);
+    expect(second.priceA - first.priceA).toEqual(10n * (4n << 64n));
+    expect(second.liquidity - first.liquidity).toEqual(10n * 200000000n);
+
+    // 15 seconds ago falls halfway between the two entries
+    const earlier = await consult(fixture, observations, 15);
+    expect(BigInt(earlier.timestamp.toString())).toEqual(start.unixTimestamp + 5n);
+    expect(BigInt(earlier.priceACumulative.toString())).toEqual(first.priceA + 5n * (4n << 64n));
+    expect(BigInt(earlier.liquidityCumulative.toString())).toEqual(first.liquidity + 5n * 200000000n);
+
+    // 5 seconds ago falls halfway between the second entry and now, at the price after the swap
+    const poolAccount = await program.account.pool.fetch(fixture.pool);
+    const price = spotPrice(BigInt(poolAccount.reserveA.toString()), BigInt(poolAccount.reserveB.toString()));
+    const later = await consult(fixture, observations, 5);
+    expect(BigInt(later.timestamp.toString())).toEqual(start.unixTimestamp + 15n);
+    expect(BigInt(later.priceACumulative.toString())).toEqual(second.priceA + 5n * price);
+
+    // Windows older than the first entry cannot be read
+    await expect(consult(fixture, observations, 21)).rejects.toThrow(/ObservationTooOld/);
+  });
+
+  it("Records observations of every pool a route trades through", async () => {
+    const fixture = await createPoolFixture();
+    await deposit(100000000, 400000000, fixture);
+    const created = await warp(0);
+    const observations = await createObservations(fixture);
+
+    await warp(5);
+    await swapRoute(1000000, 1, [{ ...fixture, observations }]);
+    const buffer = await program.account.observations.fetch(observations);
+    const poolAccount = await program.account.pool.fetch(fixture.pool);
+    expect(buffer.len).toEqual(2);
+    const latest = buffer.observations[buffer.index];
+    expect(BigInt(latest.timestamp.toString())).toEqual(created.unixTimestamp + 5n);
+    expect(latest.timestamp.toString()).toEqual(poolAccount.lastUpdate.toString());
+    // Recorded before the trade, when the pool still held 1 A for 4 B
+    expect(BigInt(latest.priceACumulative.toString()) - BigInt(buffer.observations[0].priceACumulative.toString())).toEqual(
+      5n * (4n << 64n)
+    );
+
+    // Routes may leave out the observations of a pool
+    await warp(5);
+    await swapRoute(1000000, 1, [fixture]);
+    expect((await program.account.observations.fetch(observations)).len).toEqual(2);
+  });
+
+  // Q64.64 price of the base token in the quote token
+  function spotPrice(reserveBase: bigint, reserveQuote: bigint) {
+    return (reserveQuote << 64n) / reserveBase;
+  }
+
+  async function updateOracle(fixture: PoolFixture) {
+    return program.methods.updateOracle().accountsPartial({ pool: fixture.pool, observations: null }).rpc();
+  }
+
+  async function readOracle(fixture: PoolFixture) {
+    const poolAccount = await program.account.pool.fetch(fixture.pool);
+    return {
+      priceA: BigInt(poolAccount.priceACumulative.toString()),
+      priceB: BigInt(poolAccount.priceBCumulative.toString()),
+      reserveA: BigInt(poolAccount.reserveA.toString()),
+      reserveB: BigInt(poolAccount.reserveB.toString()),
+      timestamp: BigInt(poolAccount.lastUpdate.toString()),
+    };
+  }
+
+  // Moves the bank to the next slot, which also gives it a fresh blockhash,
+  // and the clock `seconds` past its current timestamp
+  async function warp(seconds: number) {
+    const before = await context.banksClient.getClock();
+    context.warpToSlot(before.slot + 1n);
+    const after = await context.banksClient.getClock();
+    const clock = new Clock(
+      after.slot,
+      after.epochStartTimestamp,
+      after.epoch,
+      after.leaderScheduleEpoch,
+      before.unixTimestamp + BigInt(seconds)
+    );
+    context.setClock(clock);
+    return clock;
+  }
+
+  async function consult(fixture: PoolFixture, observations: PublicKey, secondsAgo: number) {
+    return program.methods.consult(secondsAgo).accountsPartial({ pool: fixture.pool, observations }).view();
+  }
+});
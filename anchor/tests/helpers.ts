@@ -0,0 +1,406 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { Keypair, PublicKey, SystemProgram, Transaction } from "@solana/web3.js";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  MINT_SIZE,
+  createAssociatedTokenAccountIdempotentInstruction,
+  createInitializeMint2Instruction,
+  createMintToInstruction,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
+import type { Amm } from "../target/types/amm";
+
+// Fixtures and instruction helpers shared by the test suites, which differ only
+// in the provider they run against
+
+export type Deadline = { unixTimestamp: { 0: anchor.BN } } | { slot: { 0: anchor.BN } };
+
+// Pricing of a pool, constant product unless an amplification or weights are given
+export interface PoolCurve {
+  amplification?: number;
+  weights?: [number, number];
+  // Raw parameters sent instead of those encoded from the fields above
+  params?: number[];
+}
+
+// Accounts of a pool and of the user trading against it
+export interface PoolFixture {
+  mintA: PublicKey;
+  mintB: PublicKey;
+  tokenProgramA: PublicKey;
+  tokenProgramB: PublicKey;
+  pool: PublicKey;
+  poolAuthority: PublicKey;
+  mintLiquidity: PublicKey;
+  liquidityLock: PublicKey;
+  poolAccountA: PublicKey;
+  poolAccountB: PublicKey;
+  userAccountA: PublicKey;
+  userAccountB: PublicKey;
+  // Price history, passed to swaps once created
+  observations?: PublicKey;
+}
+
+// What a suite runs the helpers with, read on every call as suites set it up in `beforeAll`
+export interface TestEnv {
+  provider: anchor.Provider;
+  program: Program<Amm>;
+  // Mint authority of every test mint and payer of new accounts
+  payer: Keypair;
+  // Trader and depositor
+  user: Keypair;
+  amm: PublicKey;
+  // Lamports keeping an account of `space` bytes rent exempt
+  rent(space: number): Promise<number>;
+}
+
+// Curve type and zero-padded little-endian parameters of `create_pool`
+export function curveArgs(curve: PoolCurve) {
+  let curveType: object = { constantProduct: {} };
+  let params: number[] = [];
+  if (curve.amplification !== undefined) {
+    curveType = { stable: {} };
+    params = new anchor.BN(curve.amplification).toArray("le", 8);
+  } else if (curve.weights !== undefined) {
+    curveType = { weighted: {} };
+    params = curve.weights.flatMap((weight) => new anchor.BN(weight).toArray("le", 2));
+  }
+  params = curve.params ?? params;
+  return { curveType, curveParams: [...params, ...new Array(32 - params.length).fill(0)] };
+}
+
+// Mint keypair whose address sorts strictly between the given bounds
+export function mintKeypair(bounds: { above?: PublicKey; below?: PublicKey }) {
+  for (;;) {
+    const keypair = Keypair.generate();
+    const key = keypair.publicKey.toBuffer();
+    if ((!bounds.above || key.compare(bounds.above.toBuffer()) > 0) && (!bounds.below || key.compare(bounds.below.toBuffer()) < 0)) {
+      return keypair;
+    }
+  }
+}
+
+export function ammHelpers(env: () => TestEnv) {
+  // Program addresses of the pool trading `mintA` against `mintB`, in that order
+  function poolAddresses(mintA: PublicKey, mintB: PublicKey) {
+    const { program, amm } = env();
+    const address = (...seeds: string[]) =>
+      PublicKey.findProgramAddressSync(
+        [amm.toBuffer(), mintA.toBuffer(), mintB.toBuffer(), ...seeds.map((seed) => Buffer.from(seed))],
+        program.programId
+      )[0];
+    return {
+      pool: address(),
+      poolAuthority: address("authority"),
+      mintLiquidity: address("liquidity"),
+      liquidityLock: address("lock"),
+    };
+  }
+
+  // Creates fresh mints owned by the given token programs unless provided, funds the user and creates their pool
+  async function createPoolFixture(
+    tokenProgramA: PublicKey = TOKEN_PROGRAM_ID,
+    tokenProgramB: PublicKey = TOKEN_PROGRAM_ID,
+    mints: { mintA?: PublicKey; mintB?: PublicKey } = {},
+    curve: PoolCurve = {}
+  ): Promise<PoolFixture> {
+    const { provider, payer, user, rent } = env();
+    const mintKeypairA = mints.mintA ? null : mintKeypair({ below: mints.mintB });
+    const mintA = mints.mintA ?? mintKeypairA!.publicKey;
+    const mintKeypairB = mints.mintB ? null : mintKeypair({ above: mintA });
+    const mintB = mints.mintB ?? mintKeypairB!.publicKey;
+
+    const addresses = poolAddresses(mintA, mintB);
+    const fixture: PoolFixture = {
+      mintA,
+      mintB,
+      tokenProgramA,
+      tokenProgramB,
+      ...addresses,
+      poolAccountA: getAssociatedTokenAddressSync(mintA, addresses.poolAuthority, true, tokenProgramA),
+      poolAccountB: getAssociatedTokenAddressSync(mintB, addresses.poolAuthority, true, tokenProgramB),
+      userAccountA: getAssociatedTokenAddressSync(mintA, user.publicKey, false, tokenProgramA),
+      userAccountB: getAssociatedTokenAddressSync(mintB, user.publicKey, false, tokenProgramB),
+    };
+
+    const lamports = await rent(MINT_SIZE);
+    const transaction = new Transaction();
+    for (const [keypair, mint, userAccount, tokenProgram] of [
+      [mintKeypairA, mintA, fixture.userAccountA, tokenProgramA],
+      [mintKeypairB, mintB, fixture.userAccountB, tokenProgramB],
+    ] as const) {
+      if (keypair) {
+        transaction.add(
+          SystemProgram.createAccount({
+            fromPubkey: payer.publicKey,
+            newAccountPubkey: mint,
+            space: MINT_SIZE,
+            lamports,
+            programId: tokenProgram,
+          }),
+          createInitializeMint2Instruction(mint, 6, payer.publicKey, null, tokenProgram)
+        );
+      }
+      transaction.add(
+        createAssociatedTokenAccountIdempotentInstruction(payer.publicKey, userAccount, user.publicKey, mint, tokenProgram),
+        createMintToInstruction(mint, userAccount, payer.publicKey, 1000000000, [], tokenProgram)
+      );
+    }
+    const mintKeypairs = [mintKeypairA, mintKeypairB].filter((keypair): keypair is Keypair => keypair !== null);
+    await provider.sendAndConfirm!(transaction, [payer, ...mintKeypairs]);
+
+    await createPool(fixture, curve);
+    return fixture;
+  }
+
+  // Creates a constant product pool unless an amplification or weights are given
+  async function createPool(fixture: PoolFixture, curve: PoolCurve = {}) {
+    const { program, payer, amm } = env();
+    const { curveType, curveParams } = curveArgs(curve);
+    return program.methods
+      .createPool(curveType, curveParams)
+      .accountsPartial({
+        amm,
+        pool: fixture.pool,
+        poolAuthority: fixture.poolAuthority,
+        mintLiquidity: fixture.mintLiquidity,
+        liquidityLock: fixture.liquidityLock,
+        mintA: fixture.mintA,
+        mintB: fixture.mintB,
+        poolAccountA: fixture.poolAccountA,
+        poolAccountB: fixture.poolAccountB,
+        payer: payer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgramA: fixture.tokenProgramA,
+        tokenProgramB: fixture.tokenProgramB,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([payer])
+      .rpc();
+  }
+
+  // Creates the observations account of the pool, which records a first entry
+  async function createObservations(fixture: PoolFixture) {
+    const { program, payer } = env();
+    const [observations] = PublicKey.findProgramAddressSync(
+      [fixture.pool.toBuffer(), Buffer.from("observations")],
+      program.programId
+    );
+    await program.methods
+      .createObservations()
+      .accountsPartial({ pool: fixture.pool, observations, payer: payer.publicKey })
+      .signers([payer])
+      .rpc();
+    return observations;
+  }
+
+  async function swap(
+    swapA: boolean,
+    inputAmount: number,
+    minOutputAmount: number,
+    fixture: PoolFixture,
+    deadline: Deadline | null = null
+  ) {
+    const { payer, user } = env();
+    return swapMethod(swapA, inputAmount, minOutputAmount, fixture, deadline).signers([user, payer]).rpc();
+  }
+
+  function swapMethod(
+    swapA: boolean,
+    inputAmount: number,
+    minOutputAmount: number,
+    fixture: PoolFixture,
+    deadline: Deadline | null
+  ) {
+    const { program, payer, user, amm } = env();
+    return program.methods
+      .swapExactTokensForTokens(swapA, new anchor.BN(inputAmount), new anchor.BN(minOutputAmount), deadline)
+      .accountsPartial({
+        amm,
+        pool: fixture.pool,
+        observations: fixture.observations ?? null,
+        poolAuthority: fixture.poolAuthority,
+        trader: user.publicKey,
+        mintA: fixture.mintA,
+        mintB: fixture.mintB,
+        poolAccountA: fixture.poolAccountA,
+        poolAccountB: fixture.poolAccountB,
+        traderAccountA: fixture.userAccountA,
+        traderAccountB: fixture.userAccountB,
+        payer: payer.publicKey,
+        tokenProgramA: fixture.tokenProgramA,
+        tokenProgramB: fixture.tokenProgramB,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      });
+  }
+
+  // Buys exactly `outputAmount` for at most `maxInputAmount`
+  async function swapExactOutput(
+    swapA: boolean,
+    outputAmount: number,
+    maxInputAmount: number,
+    fixture: PoolFixture,
+    deadline: Deadline | null = null
+  ) {
+    const { program, payer, user, amm } = env();
+    return program.methods
+      .swapTokensForExactTokens(swapA, new anchor.BN(outputAmount), new anchor.BN(maxInputAmount), deadline)
+      .accountsPartial({
+        amm,
+        pool: fixture.pool,
+        observations: fixture.observations ?? null,
+        poolAuthority: fixture.poolAuthority,
+        trader: user.publicKey,
+        mintA: fixture.mintA,
+        mintB: fixture.mintB,
+        poolAccountA: fixture.poolAccountA,
+        poolAccountB: fixture.poolAccountB,
+        traderAccountA: fixture.userAccountA,
+        traderAccountB: fixture.userAccountB,
+        payer: payer.publicKey,
+        tokenProgramA: fixture.tokenProgramA,
+        tokenProgramB: fixture.tokenProgramB,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, payer])
+      .rpc();
+  }
+
+  // Sells token A of the first pool for the token of the last pool that is not shared with the previous one
+  async function swapRoute(inputAmount: number, minOutputAmount: number, pools: PoolFixture[], deadline: Deadline | null = null) {
+    const { program, payer, user, amm } = env();
+    let mintIn = pools[0].mintA;
+    let tokenProgramIn = pools[0].tokenProgramA;
+    let traderAccountIn = pools[0].userAccountA;
+    let mintOut = mintIn;
+    let tokenProgramOut = tokenProgramIn;
+    let traderAccountOut = traderAccountIn;
+    for (const pool of pools) {
+      const sellA = mintOut.equals(pool.mintA);
+      mintOut = sellA ? pool.mintB : pool.mintA;
+      tokenProgramOut = sellA ? pool.tokenProgramB : pool.tokenProgramA;
+      traderAccountOut = sellA ? pool.userAccountB : pool.userAccountA;
+    }
+
+    const remainingAccounts = pools.flatMap((pool) =>
+      [
+        pool.pool,
+        pool.poolAuthority,
+        pool.mintA,
+        pool.mintB,
+        pool.poolAccountA,
+        pool.poolAccountB,
+        pool.tokenProgramA,
+        pool.tokenProgramB,
+        // The program id stands in for a pool without observations
+        pool.observations ?? program.programId,
+      ].map((pubkey, index) => ({ pubkey, isSigner: false, isWritable: [0, 4, 5].includes(index) || (index === 8 && !!pool.observations) }))
+    );
+
+    return program.methods
+      .swapRoute(new anchor.BN(inputAmount), new anchor.BN(minOutputAmount), deadline)
+      .accountsPartial({
+        amm,
+        trader: user.publicKey,
+        mintIn,
+        mintOut,
+        traderAccountIn,
+        traderAccountOut,
+        payer: payer.publicKey,
+        tokenProgramIn,
+        tokenProgramOut,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(remainingAccounts)
+      .signers([user, payer])
+      .rpc();
+  }
+
+  async function deposit(
+    amountA: number,
+    amountB: number,
+    fixture: PoolFixture,
+    minLiquidityOut = 0,
+    deadline: Deadline | null = null
+  ) {
+    const { program, payer, user, amm } = env();
+    return program.methods
+      .depositLiquidity(new anchor.BN(amountA), new anchor.BN(amountB), new anchor.BN(minLiquidityOut), deadline)
+      .accountsPartial({
+        payer: payer.publicKey,
+        amm,
+        pool: fixture.pool,
+        poolAuthority: fixture.poolAuthority,
+        depositor: user.publicKey,
+        mintLiquidity: fixture.mintLiquidity,
+        liquidityLock: fixture.liquidityLock,
+        mintA: fixture.mintA,
+        mintB: fixture.mintB,
+        poolAccountA: fixture.poolAccountA,
+        poolAccountB: fixture.poolAccountB,
+        depositorAccountLiquidity: getAssociatedTokenAddressSync(fixture.mintLiquidity, user.publicKey, true),
+        depositorAccountA: fixture.userAccountA,
+        depositorAccountB: fixture.userAccountB,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgramA: fixture.tokenProgramA,
+        tokenProgramB: fixture.tokenProgramB,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, payer])
+      .rpc();
+  }
+
+  async function withdraw(
+    amount: number,
+    fixture: PoolFixture,
+    minAmountA = 0,
+    minAmountB = 0,
+    deadline: Deadline | null = null
+  ) {
+    const { program, payer, user, amm } = env();
+    return program.methods
+      .withdrawLiquidity(new anchor.BN(amount), new anchor.BN(minAmountA), new anchor.BN(minAmountB), deadline)
+      .accountsPartial({
+        amm,
+        pool: fixture.pool,
+        poolAuthority: fixture.poolAuthority,
+        depositor: user.publicKey,
+        mintLiquidity: fixture.mintLiquidity,
+        mintA: fixture.mintA,
+        mintB: fixture.mintB,
+        poolAccountA: fixture.poolAccountA,
+        poolAccountB: fixture.poolAccountB,
+        depositorAccountLiquidity: getAssociatedTokenAddressSync(fixture.mintLiquidity, user.publicKey, true),
+        depositerAccountA: fixture.userAccountA,
+        depositerAccountB: fixture.userAccountB,
+        payer: payer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgramA: fixture.tokenProgramA,
+        tokenProgramB: fixture.tokenProgramB,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user, payer])
+      .rpc();
+  }
+
+  return {
+    poolAddresses,
+    createPoolFixture,
+    createPool,
+    createObservations,
+    swap,
+    swapMethod,
+    swapExactOutput,
+    swapRoute,
+    deposit,
+    withdraw,
+  };
+}